@@ -0,0 +1,608 @@
+//! Bucket assignment strategies, independent of folder naming and I/O.
+
+use anyhow::{Result, anyhow};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// How entries are spread across buckets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// Contiguous runs of the sorted input, sizes differing by at most one (the default).
+    #[default]
+    Count,
+    /// Entry `i` goes to bucket `i % n`.
+    RoundRobin,
+    /// Greedy size balancing: entries in `Fill` order, each into the bucket with the fewest
+    /// bytes.
+    Size,
+}
+
+impl FromStr for Strategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "count" => Ok(Strategy::Count),
+            "round-robin" => Ok(Strategy::RoundRobin),
+            "size" => Ok(Strategy::Size),
+            other => Err(anyhow!(
+                "Unknown strategy '{}'. Use count|round-robin|size",
+                other
+            )),
+        }
+    }
+}
+
+/// Which buckets of a `Strategy::Count` split carry the extra entries when they do not
+/// divide evenly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Remainder {
+    /// The first buckets get one more entry each (the default).
+    #[default]
+    First,
+    /// The last buckets get one more entry each, so the earlier ones are uniform.
+    Last,
+}
+
+impl FromStr for Remainder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "first" => Ok(Remainder::First),
+            "last" => Ok(Remainder::Last),
+            other => Err(anyhow!("Unknown remainder '{}'. Use first|last", other)),
+        }
+    }
+}
+
+/// The order `Strategy::Size` visits entries in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fill {
+    /// Largest entries first (longest-processing-time), which balances best (the default).
+    #[default]
+    LargestFirst,
+    /// Smallest entries first; the large entries placed last decide the final imbalance.
+    SmallestFirst,
+}
+
+impl FromStr for Fill {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "largest-first" => Ok(Fill::LargestFirst),
+            "smallest-first" => Ok(Fill::SmallestFirst),
+            other => Err(anyhow!(
+                "Unknown fill '{}'. Use largest-first|smallest-first",
+                other
+            )),
+        }
+    }
+}
+
+/// The smallest a bucket may be before it is merged into a neighbour: a number of entries
+/// (`3`) or of bytes (`4096B`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MinBucket {
+    Entries(usize),
+    Bytes(u64),
+}
+
+impl FromStr for MinBucket {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parsed = match s.strip_suffix('B') {
+            Some(bytes) => bytes.parse().map(MinBucket::Bytes),
+            None => s.parse().map(MinBucket::Entries),
+        };
+        parsed.map_err(|_| {
+            anyhow!(
+                "Invalid minimum bucket size '{}'. Use a number of entries (3) or bytes (4096B)",
+                s
+            )
+        })
+    }
+}
+
+/// Assign `files` to `n` buckets using `strategy`.
+///
+/// Keys are 0-based bucket indices and every bucket from `0` to `n - 1` is present, even when
+/// empty. Within a bucket, files keep their input order.
+///
+/// ```
+/// use refolder::{Strategy, assign};
+/// use std::path::PathBuf;
+///
+/// let files: Vec<PathBuf> = (0..5).map(|i| PathBuf::from(format!("f{}", i))).collect();
+///
+/// let by_count = assign(files.clone(), 2, Strategy::Count);
+/// assert_eq!(by_count[&0], vec![PathBuf::from("f0"), PathBuf::from("f1"), PathBuf::from("f2")]);
+/// assert_eq!(by_count[&1], vec![PathBuf::from("f3"), PathBuf::from("f4")]);
+///
+/// let round_robin = assign(files, 2, Strategy::RoundRobin);
+/// assert_eq!(round_robin[&0], vec![PathBuf::from("f0"), PathBuf::from("f2"), PathBuf::from("f4")]);
+/// assert_eq!(round_robin[&1], vec![PathBuf::from("f1"), PathBuf::from("f3")]);
+/// ```
+///
+/// `Strategy::Size` reads each file's size from disk; files that cannot be stat'd count as
+/// empty.
+pub fn assign(files: Vec<PathBuf>, n: usize, strategy: Strategy) -> BTreeMap<usize, Vec<PathBuf>> {
+    assign_with(files, n, strategy, Remainder::First, Fill::LargestFirst)
+}
+
+/// `assign` with the extra entries of a `Strategy::Count` split placed per `remainder`, and
+/// a `Strategy::Size` packing visiting entries in `fill` order.
+pub(crate) fn assign_with(
+    files: Vec<PathBuf>,
+    n: usize,
+    strategy: Strategy,
+    remainder: Remainder,
+    fill: Fill,
+) -> BTreeMap<usize, Vec<PathBuf>> {
+    let sizes: Vec<u64> = match strategy {
+        Strategy::Size => files
+            .iter()
+            .map(|f| fs::metadata(f).map(|m| m.len()).unwrap_or(0))
+            .collect(),
+        _ => Vec::new(),
+    };
+    assign_sized(files, &sizes, n, strategy, remainder, fill)
+}
+
+/// `assign` with the entry sizes already known (`sizes[i]` belongs to `files[i]`), so
+/// `Strategy::Size` does not stat anything. Other strategies ignore `sizes`.
+pub(crate) fn assign_sized(
+    files: Vec<PathBuf>,
+    sizes: &[u64],
+    n: usize,
+    strategy: Strategy,
+    remainder: Remainder,
+    fill: Fill,
+) -> BTreeMap<usize, Vec<PathBuf>> {
+    let buckets = match strategy {
+        Strategy::Count => partition(files, n, remainder),
+        Strategy::RoundRobin => round_robin(files, n),
+        Strategy::Size => balance_by_size(files, sizes, n, fill),
+    };
+    buckets.into_iter().enumerate().collect()
+}
+
+/// Partition `files` into `n` buckets as evenly as possible, the extra entries going to the
+/// buckets `remainder` names. If there are fewer files than buckets, some buckets will be
+/// empty.
+///
+/// Paths are moved out of `files` into their buckets, never cloned.
+pub(crate) fn partition(files: Vec<PathBuf>, n: usize, remainder: Remainder) -> Vec<Vec<PathBuf>> {
+    let mut files = files.into_iter();
+    run_lengths(files.len(), n, remainder)
+        .into_iter()
+        .map(|take| files.by_ref().take(take).collect())
+        .collect()
+}
+
+/// Size of each of the `n` contiguous runs `partition` cuts `total` entries into.
+fn run_lengths(total: usize, n: usize, remainder: Remainder) -> Vec<usize> {
+    if n == 0 {
+        return Vec::new();
+    }
+    let (base, rem) = (total / n, total % n);
+    (0..n)
+        .map(|i| {
+            let extra = match remainder {
+                Remainder::First => i < rem,
+                Remainder::Last => i >= n - rem,
+            };
+            base + usize::from(extra)
+        })
+        .collect()
+}
+
+/// Enforce a byte ceiling on `buckets` after they were filled by count: an entry that would
+/// take a non-empty bucket past `max_bytes` is moved out, and the spilled entries are packed
+/// in order into extra buckets appended after the existing ones. An entry larger than
+/// `max_bytes` on its own still gets a bucket, alone.
+pub(crate) fn cap_bytes(
+    buckets: BTreeMap<usize, Vec<PathBuf>>,
+    size_of: impl Fn(&Path) -> u64,
+    max_bytes: u64,
+) -> BTreeMap<usize, Vec<PathBuf>> {
+    let mut capped: Vec<Vec<PathBuf>> = Vec::with_capacity(buckets.len());
+    let mut spilled = Vec::new();
+    for bucket in buckets.into_values() {
+        let (mut kept, mut total) = (Vec::new(), 0u64);
+        for file in bucket {
+            let size = size_of(&file);
+            if !kept.is_empty() && total + size > max_bytes {
+                spilled.push((file, size));
+            } else {
+                total += size;
+                kept.push(file);
+            }
+        }
+        capped.push(kept);
+    }
+
+    let first_extra = capped.len();
+    let mut total = 0u64;
+    for (file, size) in spilled {
+        if capped.len() > first_extra && total + size <= max_bytes {
+            capped.last_mut().expect("an extra bucket").push(file);
+            total += size;
+        } else {
+            capped.push(vec![file]);
+            total = size;
+        }
+    }
+    capped.into_iter().enumerate().collect()
+}
+
+/// Merge every bucket below `min` into the next one, and a small last bucket into the one
+/// before it, so only a lone bucket can stay under the floor. Buckets keep their order and
+/// are renumbered from 0.
+pub(crate) fn merge_small(
+    buckets: BTreeMap<usize, Vec<PathBuf>>,
+    size_of: impl Fn(&Path) -> u64,
+    min: MinBucket,
+) -> BTreeMap<usize, Vec<PathBuf>> {
+    let small = |bucket: &[PathBuf]| match min {
+        MinBucket::Entries(n) => bucket.len() < n,
+        MinBucket::Bytes(n) => bucket.iter().map(|f| size_of(f)).sum::<u64>() < n,
+    };
+    let mut merged: Vec<Vec<PathBuf>> = Vec::new();
+    let mut carried = Vec::new();
+    for bucket in buckets.into_values() {
+        carried.extend(bucket);
+        if !small(&carried) {
+            merged.push(std::mem::take(&mut carried));
+        }
+    }
+    match merged.last_mut() {
+        Some(last) => last.extend(carried),
+        None => merged.push(carried),
+    }
+    merged.into_iter().enumerate().collect()
+}
+
+/// Which bucket each folder slot should take so that as many entries as possible stay where
+/// they are: `overlap(slot, bucket)` counts the entries of `bucket` already in that slot's
+/// folder. Pairs are matched greedily from the largest overlap down (lowest bucket, then
+/// slot, on ties); buckets left over fill the remaining slots in order.
+pub(crate) fn match_origins(
+    buckets: &[Vec<PathBuf>],
+    overlap: impl Fn(usize, &[PathBuf]) -> usize,
+) -> Vec<usize> {
+    let n = buckets.len();
+    let mut pairs: Vec<(usize, usize, usize)> = Vec::new();
+    for (bucket, entries) in buckets.iter().enumerate() {
+        for slot in 0..n {
+            let shared = overlap(slot, entries);
+            if shared > 0 {
+                pairs.push((shared, bucket, slot));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+
+    let mut slot_of: Vec<Option<usize>> = vec![None; n];
+    let mut taken = vec![false; n];
+    for (_, bucket, slot) in pairs {
+        if slot_of[bucket].is_none() && !taken[slot] {
+            slot_of[bucket] = Some(slot);
+            taken[slot] = true;
+        }
+    }
+    let mut free = (0..n).filter(|&slot| !taken[slot]);
+    let mut order = vec![0; n];
+    for (bucket, slot) in slot_of.into_iter().enumerate() {
+        let slot = slot.unwrap_or_else(|| free.next().expect("a free slot per bucket"));
+        order[slot] = bucket;
+    }
+    order
+}
+
+/// Deal `files` out one at a time: entry `i` goes to bucket `i % n`.
+fn round_robin(files: Vec<PathBuf>, n: usize) -> Vec<Vec<PathBuf>> {
+    let mut buckets: Vec<Vec<PathBuf>> = vec![Vec::new(); n];
+    if n == 0 {
+        return buckets;
+    }
+    for (i, file) in files.into_iter().enumerate() {
+        buckets[i % n].push(file);
+    }
+    buckets
+}
+
+/// Greedy packing: visit files in `fill` order (largest to smallest is the classic
+/// longest-processing-time rule) and put each into the bucket with the fewest bytes so far
+/// (lowest index on ties).
+fn balance_by_size(files: Vec<PathBuf>, sizes: &[u64], n: usize, fill: Fill) -> Vec<Vec<PathBuf>> {
+    let mut buckets: Vec<Vec<PathBuf>> = vec![Vec::new(); n];
+    if n == 0 {
+        return buckets;
+    }
+    for (file, (target, _)) in files.into_iter().zip(size_fit(sizes, n, fill)) {
+        buckets[target].push(file);
+    }
+    buckets
+}
+
+/// For each entry of `sizes`, the bucket `balance_by_size` picks and that bucket's byte total
+/// just before the entry went in. `n` must be non-zero.
+fn size_fit(sizes: &[u64], n: usize, fill: Fill) -> Vec<(usize, u64)> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    // Stable sort keeps input order among equal sizes
+    match fill {
+        Fill::LargestFirst => order.sort_by(|&a, &b| sizes[b].cmp(&sizes[a])),
+        Fill::SmallestFirst => order.sort_by_key(|&i| sizes[i]),
+    }
+
+    let mut totals = vec![0u64; n];
+    let mut chosen = vec![(0usize, 0u64); sizes.len()];
+    for idx in order {
+        let (target, &total) = totals
+            .iter()
+            .enumerate()
+            .min_by_key(|&(i, total)| (*total, i))
+            .expect("n > 0");
+        chosen[idx] = (target, total);
+        totals[target] += sizes[idx];
+    }
+    chosen
+}
+
+/// Why `assign_sized` puts each entry where it does, in input order, e.g.
+/// `bucket 2 (round-robin i=5 → 5%3)`. `sizes` has one entry per file; buckets are 0-based,
+/// as in `assign`.
+pub(crate) fn reasons(
+    sizes: &[u64],
+    n: usize,
+    strategy: Strategy,
+    remainder: Remainder,
+    fill: Fill,
+) -> Vec<String> {
+    if n == 0 {
+        return Vec::new();
+    }
+    let total = sizes.len();
+    match strategy {
+        Strategy::Count => run_lengths(total, n, remainder)
+            .into_iter()
+            .enumerate()
+            .flat_map(|(bucket, run)| std::iter::repeat_n((bucket, run), run))
+            .enumerate()
+            .map(|(i, (bucket, run))| {
+                format!("bucket {} (count i={} in a run of {})", bucket, i, run)
+            })
+            .collect(),
+        Strategy::RoundRobin => (0..total)
+            .map(|i| format!("bucket {} (round-robin i={} → {}%{})", i % n, i, i, n))
+            .collect(),
+        Strategy::Size => size_fit(sizes, n, fill)
+            .into_iter()
+            .zip(sizes)
+            .map(|((bucket, total), size)| {
+                format!(
+                    "bucket {} (size-fit {} B into the smallest bucket at {} B)",
+                    bucket, size, total
+                )
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(n: usize) -> Vec<PathBuf> {
+        (0..n).map(|i| PathBuf::from(format!("f{}", i))).collect()
+    }
+
+    fn lens(buckets: &BTreeMap<usize, Vec<PathBuf>>) -> Vec<usize> {
+        buckets.values().map(|b| b.len()).collect()
+    }
+
+    #[test]
+    fn match_origins_keeps_the_biggest_overlaps() {
+        let buckets = vec![names(2), vec![PathBuf::from("x")], vec![PathBuf::from("y")]];
+        // Bucket 0 mostly lives in slot 2, bucket 2 in slot 0; bucket 1 has no origin
+        let origin = |slot: usize, entries: &[PathBuf]| match (slot, entries.len()) {
+            (2, 2) => 2,
+            (0, 1) if entries[0] == Path::new("y") => 1,
+            (2, 1) if entries[0] == Path::new("y") => 1,
+            _ => 0,
+        };
+        assert_eq!(match_origins(&buckets, origin), vec![2, 1, 0]);
+        assert_eq!(match_origins(&buckets, |_, _| 0), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn assign_count() {
+        let buckets = assign(names(10), 3, Strategy::Count);
+        assert_eq!(buckets.keys().copied().collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(lens(&buckets), vec![4, 3, 3]);
+        assert_eq!(buckets[&1][0], PathBuf::from("f4"));
+    }
+
+    #[test]
+    fn assign_round_robin() {
+        let buckets = assign(names(7), 3, Strategy::RoundRobin);
+        assert_eq!(lens(&buckets), vec![3, 2, 2]);
+        assert_eq!(buckets[&2], vec![PathBuf::from("f2"), PathBuf::from("f5")]);
+    }
+
+    #[test]
+    fn assign_size() {
+        let files = names(5);
+        let sizes = [10, 1, 7, 3, 2];
+        let buckets = balance_by_size(files, &sizes, 2, Fill::LargestFirst);
+        // 10 -> b0, 7 -> b1, 3 -> b1, 2 -> b0 (tie), 1 -> b1: totals 12 and 11
+        assert_eq!(
+            buckets,
+            vec![
+                vec![PathBuf::from("f0"), PathBuf::from("f4")],
+                vec![
+                    PathBuf::from("f1"),
+                    PathBuf::from("f2"),
+                    PathBuf::from("f3")
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn remainder_picks_the_longer_buckets() {
+        let lens = |remainder| {
+            partition(names(10), 3, remainder)
+                .iter()
+                .map(Vec::len)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lens(Remainder::First), vec![4, 3, 3]);
+        assert_eq!(lens(Remainder::Last), vec![3, 3, 4]);
+    }
+
+    #[test]
+    fn cap_bytes_spills_oversized_entries() {
+        let files = names(6);
+        let sizes = [10, 500, 10, 10, 10, 10];
+        let size_of = |f: &Path| sizes[files.iter().position(|p| p == f).unwrap()];
+
+        let buckets = cap_bytes(assign(files.clone(), 2, Strategy::Count), size_of, 100);
+        assert_eq!(
+            buckets.into_values().collect::<Vec<_>>(),
+            vec![
+                vec![PathBuf::from("f0"), PathBuf::from("f2")],
+                vec![
+                    PathBuf::from("f3"),
+                    PathBuf::from("f4"),
+                    PathBuf::from("f5")
+                ],
+                vec![PathBuf::from("f1")],
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_small_folds_a_tiny_last_bucket_back() {
+        let files = names(7);
+        let sizes = [40, 40, 40, 40, 40, 40, 5];
+        let size_of = |f: &Path| sizes[files.iter().position(|p| p == f).unwrap()];
+        let buckets = cap_bytes(assign(files.clone(), 1, Strategy::Count), size_of, 80);
+        assert_eq!(lens(&buckets), vec![2, 2, 2, 1]);
+
+        let merged = merge_small(buckets.clone(), size_of, MinBucket::Bytes(50));
+        assert_eq!(lens(&merged), vec![2, 2, 3]);
+        assert_eq!(merged.into_values().flatten().collect::<Vec<_>>(), files);
+
+        // A small bucket in the middle goes into the next one
+        let merged = merge_small(
+            assign(files.clone(), 4, Strategy::Count),
+            size_of,
+            MinBucket::Entries(3),
+        );
+        assert_eq!(lens(&merged), vec![4, 3]);
+        // A lone bucket stays, however small
+        let merged = merge_small(
+            assign(names(1), 1, Strategy::Count),
+            size_of,
+            MinBucket::Entries(3),
+        );
+        assert_eq!(lens(&merged), vec![1]);
+        assert!("12x".parse::<MinBucket>().is_err());
+        assert_eq!(
+            "4096B".parse::<MinBucket>().unwrap(),
+            MinBucket::Bytes(4096)
+        );
+    }
+
+    #[test]
+    fn partition_large_input() {
+        let files = names(250_003);
+        let buckets = partition(files.clone(), 7, Remainder::First);
+
+        let sizes: Vec<usize> = buckets.iter().map(|b| b.len()).collect();
+        assert_eq!(
+            sizes,
+            vec![35_715, 35_715, 35_715, 35_715, 35_715, 35_714, 35_714]
+        );
+        // contiguous runs: concatenating the buckets gives back the input order
+        assert_eq!(buckets.concat(), files);
+    }
+
+    #[test]
+    fn fill_order_changes_balance() {
+        // Largest first puts the 6 alone and the ones opposite it: 6 vs 6. Smallest first
+        // spreads the ones 3 vs 3, then the 6 lands on top of one side: 9 vs 3.
+        let sizes = [1, 1, 1, 6, 1, 1, 1];
+        let max_total = |fill| {
+            let buckets = balance_by_size(names(sizes.len()), &sizes, 2, fill);
+            buckets
+                .iter()
+                .map(|b| {
+                    b.iter()
+                        .map(|f| sizes[f.to_string_lossy()[1..].parse::<usize>().unwrap()])
+                        .sum::<u64>()
+                })
+                .max()
+                .unwrap()
+        };
+        let (largest, smallest) = (
+            max_total(Fill::LargestFirst),
+            max_total(Fill::SmallestFirst),
+        );
+        assert_eq!((largest, smallest), (6, 9));
+    }
+
+    #[test]
+    fn reasons_match_assignment() {
+        let files = names(7);
+        let explained = reasons(
+            &[0; 7],
+            3,
+            Strategy::RoundRobin,
+            Remainder::First,
+            Fill::LargestFirst,
+        );
+        assert_eq!(explained[5], "bucket 2 (round-robin i=5 → 5%3)");
+
+        let buckets = assign_with(
+            files.clone(),
+            3,
+            Strategy::Count,
+            Remainder::Last,
+            Fill::LargestFirst,
+        );
+        let explained = reasons(
+            &[0; 7],
+            3,
+            Strategy::Count,
+            Remainder::Last,
+            Fill::LargestFirst,
+        );
+        for (i, reason) in explained.iter().enumerate() {
+            let bucket = buckets
+                .iter()
+                .find(|(_, b)| b.contains(&files[i]))
+                .unwrap()
+                .0;
+            assert!(
+                reason.starts_with(&format!("bucket {} ", bucket)),
+                "{}",
+                reason
+            );
+        }
+    }
+
+    #[test]
+    fn assign_keeps_empty_buckets() {
+        for strategy in [Strategy::Count, Strategy::RoundRobin, Strategy::Size] {
+            let buckets = assign(names(1), 3, strategy);
+            assert_eq!(lens(&buckets), vec![1, 0, 0]);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Append-only journal of runs, in the manifest's CSV format.
+//!
+//! Every real run appends a `run,<unix nanos>` row holding the time it started and one
+//! `plan,<source>,<destination>` row per entry it is about to move. As each entry is moved, a
+//! `move,<source>,<destination>` row follows, so a run that is killed part way leaves a
+//! record of what it still had to do.
+
+use crate::manifest::{parse_rows, push_row};
+use crate::{Plan, PlannedMove};
+use anyhow::{Context, Result, anyhow};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A run being appended to the journal as it goes.
+pub struct Writer {
+    file: File,
+    path: PathBuf,
+}
+
+impl Writer {
+    /// Open the journal at `path` and record a run that started at `started` and will carry
+    /// out `plan`.
+    pub fn begin(path: &Path, started: SystemTime, plan: &Plan) -> Result<Writer> {
+        let nanos = started
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let mut out = String::new();
+        push_row(&mut out, &["run", &nanos.to_string()]);
+        for m in plan.moves().filter(|m| !m.is_noop()) {
+            push_row(
+                &mut out,
+                &["plan", &m.src.to_string_lossy(), &m.dest.to_string_lossy()],
+            );
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed opening journal {}", path.display()))?;
+        let mut writer = Writer {
+            file,
+            path: path.to_path_buf(),
+        };
+        writer.write(&out)?;
+        Ok(writer)
+    }
+
+    /// Record that `m` has been moved.
+    pub fn record(&mut self, m: &PlannedMove) -> Result<()> {
+        let mut out = String::new();
+        push_row(
+            &mut out,
+            &["move", &m.src.to_string_lossy(), &m.dest.to_string_lossy()],
+        );
+        self.write(&out)
+    }
+
+    fn write(&mut self, rows: &str) -> Result<()> {
+        self.file
+            .write_all(rows.as_bytes())
+            .with_context(|| format!("Failed writing journal {}", self.path.display()))
+    }
+}
+
+/// The moves the last run in the journal at `path` planned but did not record as done, in
+/// plan order. Journals written before runs recorded their plan have nothing pending.
+pub fn pending(path: &Path) -> Result<Vec<PlannedMove>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed reading journal {}", path.display()))?;
+
+    let mut planned: Vec<PlannedMove> = Vec::new();
+    let mut done: HashSet<(PathBuf, PathBuf)> = HashSet::new();
+    for row in parse_rows(&content) {
+        match row.as_slice() {
+            [kind, _] if kind == "run" => {
+                planned.clear();
+                done.clear();
+            }
+            [kind, src, dest] if kind == "plan" => {
+                planned.push(PlannedMove::new(PathBuf::from(src), PathBuf::from(dest)))
+            }
+            [kind, src, dest] if kind == "move" => {
+                done.insert((PathBuf::from(src), PathBuf::from(dest)));
+            }
+            _ => {}
+        }
+    }
+    planned.retain(|m| !done.contains(&(m.src.clone(), m.dest.clone())));
+    Ok(planned)
+}
+
+/// Start time of the most recent run in the journal at `path`; `None` if there is no journal
+/// yet or it records no runs.
+pub fn last_run(path: &Path) -> Result<Option<SystemTime>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed reading journal {}", path.display()))?;
+
+    let mut last = None;
+    for row in parse_rows(&content) {
+        if let [kind, nanos] = row.as_slice()
+            && kind == "run"
+        {
+            let nanos: u64 = nanos
+                .parse()
+                .map_err(|_| anyhow!("Malformed run time '{}' in {}", nanos, path.display()))?;
+            last = Some(UNIX_EPOCH + Duration::from_nanos(nanos));
+        }
+    }
+    Ok(last)
+}
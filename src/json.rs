@@ -0,0 +1,194 @@
+//! Minimal JSON rendering for plans, written by hand like the CSV manifest, and a reader
+//! for the flat string objects of `--group-map`.
+
+use crate::{Plan, PlannedMove, RunReport};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+/// `s` as a quoted JSON string.
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `path` as a JSON string (lossy for non-UTF-8 paths).
+pub fn path(path: &Path) -> String {
+    string(&path.to_string_lossy())
+}
+
+/// One move as a JSON object: `{"src":..,"dest":..}`.
+pub fn move_object(m: &PlannedMove) -> String {
+    format!("{{\"src\":{},\"dest\":{}}}", path(&m.src), path(&m.dest))
+}
+
+/// `plan` as a single-line JSON object:
+/// `{"folders":[{"path":..,"moves":[{"src":..,"dest":..}]}],"skipped":[..]}`.
+pub fn plan(plan: &Plan) -> String {
+    let folders: Vec<String> = plan
+        .folders
+        .iter()
+        .map(|folder| {
+            let moves: Vec<String> = folder.moves.iter().map(move_object).collect();
+            format!(
+                "{{\"path\":{},\"moves\":[{}]}}",
+                path(&folder.path),
+                moves.join(",")
+            )
+        })
+        .collect();
+    let skipped: Vec<String> = plan.skipped.iter().map(|p| path(p)).collect();
+    format!(
+        "{{\"folders\":[{}],\"skipped\":[{}]}}",
+        folders.join(","),
+        skipped.join(",")
+    )
+}
+
+/// `report` as a single-line JSON object; see `RunReport::to_json`.
+pub fn report(report: &RunReport) -> String {
+    let created: Vec<String> = report.created.iter().map(|p| path(p)).collect();
+    let errors: Vec<String> = report.walk_errors.iter().map(|e| string(e)).collect();
+    let base_errors: Vec<String> = report.base_errors.iter().map(|e| string(e)).collect();
+    format!(
+        "{{\"matched\":{},\"folders\":{},\"created\":[{}],\"moved\":{},\"skipped\":{},\"unreadable\":{},\"bytes_moved\":{},\"cancelled\":{},\"errors\":[{}],\"base_errors\":[{}]}}",
+        report.matched,
+        report.folders,
+        created.join(","),
+        report.moved,
+        report.skipped,
+        report.walk_errors.len(),
+        report.bytes_moved,
+        report.cancelled,
+        errors.join(","),
+        base_errors.join(",")
+    )
+}
+
+/// Parse a flat JSON object of strings, `{"a.jpg": "cat", ...}`. Any other shape is an
+/// error naming what was expected.
+pub fn parse_string_object(content: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut chars = content.chars().peekable();
+    let mut object = BTreeMap::new();
+    expect(&mut chars, '{')?;
+    if skip_whitespace(&mut chars) == Some('}') {
+        chars.next();
+    } else {
+        loop {
+            let key = parse_string(&mut chars)?;
+            expect(&mut chars, ':')?;
+            let value = parse_string(&mut chars)?;
+            object.insert(key, value);
+            match skip_whitespace(&mut chars) {
+                Some(',') => chars.next(),
+                Some('}') => {
+                    chars.next();
+                    break;
+                }
+                _ => return Err("expected ',' or '}'".to_string()),
+            };
+        }
+    }
+    match skip_whitespace(&mut chars) {
+        None => Ok(object),
+        Some(_) => Err("unexpected text after the object".to_string()),
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) -> Option<char> {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    chars.peek().copied()
+}
+
+fn expect(chars: &mut Peekable<Chars>, want: char) -> Result<(), String> {
+    match skip_whitespace(chars) {
+        Some(c) if c == want => {
+            chars.next();
+            Ok(())
+        }
+        _ => Err(format!("expected '{}'", want)),
+    }
+}
+
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    expect(chars, '"').map_err(|_| "expected a string".to_string())?;
+    let mut out = String::new();
+    loop {
+        match chars.next().ok_or("unterminated string")? {
+            '"' => return Ok(out),
+            '\\' => match chars.next().ok_or("unterminated string")? {
+                '"' => out.push('"'),
+                '\\' => out.push('\\'),
+                '/' => out.push('/'),
+                'b' => out.push('\u{8}'),
+                'f' => out.push('\u{c}'),
+                'n' => out.push('\n'),
+                'r' => out.push('\r'),
+                't' => out.push('\t'),
+                'u' => {
+                    let mut code = parse_hex4(chars)?;
+                    // A surrogate pair spells one character outside the BMP
+                    if (0xd800..0xdc00).contains(&code) {
+                        if chars.next() != Some('\\') || chars.next() != Some('u') {
+                            return Err("unpaired surrogate".to_string());
+                        }
+                        let low = parse_hex4(chars)?;
+                        code =
+                            0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                    }
+                    out.push(char::from_u32(code).ok_or("invalid \\u escape")?);
+                }
+                other => return Err(format!("invalid escape '\\{}'", other)),
+            },
+            c => out.push(c),
+        }
+    }
+}
+
+fn parse_hex4(chars: &mut Peekable<Chars>) -> Result<u32, String> {
+    let hex: String = chars.take(4).collect();
+    u32::from_str_radix(&hex, 16).map_err(|_| format!("invalid \\u escape '{}'", hex))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlannedFolder;
+    use std::path::PathBuf;
+
+    #[test]
+    fn plan_json_escapes_paths() {
+        let plan = Plan {
+            folders: vec![PlannedFolder {
+                path: PathBuf::from("/b/group-1"),
+                moves: vec![PlannedMove::new(
+                    PathBuf::from("/b/say \"hi\".txt"),
+                    PathBuf::from("/b/group-1/say \"hi\".txt"),
+                )],
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            super::plan(&plan),
+            r#"{"folders":[{"path":"/b/group-1","moves":[{"src":"/b/say \"hi\".txt","dest":"/b/group-1/say \"hi\".txt"}]}],"skipped":[]}"#
+        );
+        assert_eq!(string("a\\b\n\u{1}"), r#""a\\b\n\u0001""#);
+    }
+}
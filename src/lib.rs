@@ -1,449 +1,6174 @@
+//! Core library functions used by `main` and by tests.
+//!
+//! Status messages go through the `log` facade: moves at debug level, outcomes such as
+//! "nothing to do" at info and skipped entries at warn. The host installs the logger; the
+//! dry-run preview, `print_plan` JSON and JSON Lines output are written directly.
+
 use anyhow::{Context, Result, anyhow};
 use globwalk::GlobWalkerBuilder;
-use std::collections::BTreeMap;
+use log::{debug, info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-/// Core library functions used by `main` and by tests.
+mod assign;
+mod hash;
+mod journal;
+mod json;
+mod manifest;
+mod mapping;
+mod naming;
+mod sort;
+mod space;
+mod walk;
 
-/// Bold ANSI codes for terminal output
-const BOLD_START: &str = "\x1b[1;34m";
-const BOLD_END: &str = "\x1b[0m";
+pub use assign::{Fill, MinBucket, Remainder, Strategy, assign};
+pub use naming::{
+    OnCollision, Pad, RenameCase, SuffixStyle, UnicodeForm, folder_name, render_file_template,
+    render_template,
+};
 
-/// Public API: run the refolder operation.
-pub fn run(
-    base_path: &str,
-    matching: &str,
-    subfolders: usize,
-    prefix: &str,
-    suffix: &str,
-    recursive: bool,
-    dry_run: bool,
-    force: bool,
-) -> Result<()> {
-    if subfolders == 0 {
-        return Err(anyhow!("subfolders must be greater than zero"));
-    }
+/// Colors and tree-drawing glyphs for the dry-run preview. The default is the Unicode tree
+/// with folder names in bold blue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    /// Written before each folder name, e.g. an ANSI color code.
+    pub folder_start: String,
+    /// Written after each folder name, undoing `folder_start`.
+    pub folder_end: String,
+    /// Connector for an entry with more siblings below it.
+    pub branch: String,
+    /// Connector for the last entry at its level.
+    pub last: String,
+    /// Indent under a parent that has more siblings below it.
+    pub pipe: String,
+    /// Indent under a parent that was the last at its level.
+    pub blank: String,
+}
 
-    let base = Path::new(base_path);
-    if !base.exists() {
-        return Err(anyhow!("Path '{}' does not exist", base.display()));
+impl Theme {
+    /// Box-drawing tree with bold blue folder names (the default).
+    pub fn unicode() -> Self {
+        Theme {
+            folder_start: "\x1b[1;34m".into(),
+            folder_end: "\x1b[0m".into(),
+            branch: "├── ".into(),
+            last: "└── ".into(),
+            pipe: "│   ".into(),
+            blank: "    ".into(),
+        }
     }
-    if !base.is_dir() {
-        return Err(anyhow!("Path '{}' is not a directory", base.display()));
+
+    /// Plain ASCII tree for terminals and logs without box-drawing characters.
+    pub fn ascii() -> Self {
+        Theme {
+            branch: "|-- ".into(),
+            last: "`-- ".into(),
+            pipe: "|   ".into(),
+            ..Theme::unicode()
+        }
     }
 
-    // 1) Collect files to operate on. If files live under existing target folders (prefix-<i>),
-    // treat them as sources as well so we can "redo" distributions.
-    let files = collect_files(base, matching, recursive, prefix)?;
+    /// The same glyphs without any color codes.
+    pub fn without_color(self) -> Self {
+        Theme {
+            folder_start: String::new(),
+            folder_end: String::new(),
+            ..self
+        }
+    }
+}
 
-    if files.is_empty() {
-        println!("No files matched pattern. Nothing to do.");
-        return Ok(());
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::unicode()
     }
+}
 
-    // 2) Partition into buckets as evenly as possible
-    let buckets = partition(files, subfolders);
+/// Which kinds of directory entries are collected and distributed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Entries {
+    /// Regular files only (the default).
+    #[default]
+    Files,
+    /// Directories only. Matched directories are moved whole, not recursed into.
+    Dirs,
+    /// Both files and directories.
+    Both,
+}
 
-    // 3) For each bucket, create folder name and move files
-    let mut planned_moves: Vec<(String, String)> = Vec::new();
+impl Entries {
+    fn accepts(self, entry: &CollectedFile) -> bool {
+        match self {
+            Entries::Files => entry.is_file,
+            Entries::Dirs => entry.is_dir,
+            Entries::Both => entry.is_file || entry.is_dir,
+        }
+    }
+}
 
-    for (i, bucket) in buckets.into_iter().enumerate() {
-        let folder_name = format_folder_name(prefix, i + 1, suffix)?;
-        let folder_path = base.join(&folder_name);
+impl FromStr for Entries {
+    type Err = anyhow::Error;
 
-        // Record folder creation and moves first (for dry-run printing)
-        for src in bucket {
-            let file_name = src
-                .file_name()
-                .and_then(|s| s.to_str())
-                .ok_or_else(|| anyhow!("Invalid filename for {}", src.display()))?;
-            let dest = folder_path.join(file_name);
-            planned_moves.push((src.display().to_string(), dest.display().to_string()));
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "files" => Ok(Entries::Files),
+            "dirs" => Ok(Entries::Dirs),
+            "both" => Ok(Entries::Both),
+            other => Err(anyhow!(
+                "Unknown entries kind '{}'. Use files|dirs|both",
+                other
+            )),
         }
+    }
+}
 
-        // If not dry-run, perform actual creation and moving
-        if !dry_run {
-            if folder_path.exists() {
-                if !folder_path.is_dir() {
-                    return Err(anyhow!(
-                        "Destination path {} exists and is not a directory",
-                        folder_path.display()
-                    ));
-                }
-            } else {
-                fs::create_dir_all(&folder_path).with_context(|| {
-                    format!("Failed to create directory {}", folder_path.display())
-                })?;
-            }
+/// Order of the folders in the dry-run preview. Only the listing changes, never the names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FolderOrder {
+    /// By folder name (the default).
+    #[default]
+    Name,
+    /// Most entries first.
+    Count,
+    /// Most bytes first.
+    Size,
+}
 
-            for (src_str, dest_str) in planned_moves
-                .iter()
-                .filter(|(_, d)| d.starts_with(&folder_path.display().to_string()))
-            {
-                let src = PathBuf::from(src_str);
-                let dest = PathBuf::from(dest_str);
+impl FromStr for FolderOrder {
+    type Err = anyhow::Error;
 
-                // Skip identical (redo safe)
-                if src == dest {
-                    continue;
-                }
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "name" => Ok(FolderOrder::Name),
+            "count" => Ok(FolderOrder::Count),
+            "size" => Ok(FolderOrder::Size),
+            other => Err(anyhow!(
+                "Unknown folder order '{}'. Use name|count|size",
+                other
+            )),
+        }
+    }
+}
 
-                if dest.exists() {
-                    if !force {
-                        return Err(anyhow!(
-                            "Destination file {} already exists (use --force to overwrite)",
-                            dest.display()
-                        ));
-                    } else {
-                        fs::remove_file(&dest).with_context(|| {
-                            format!(
-                                "Failed removing existing destination file {}",
-                                dest.display()
-                            )
-                        })?;
-                    }
-                }
+/// Order of the entries within each folder in the plan, the manifest and the preview. The
+/// order of a directory listing on disk is up to the filesystem and never changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryOrder {
+    /// By destination file name.
+    Name,
+    /// Largest first; ties in name order.
+    Size,
+    /// Oldest modification time first; ties in name order.
+    Mtime,
+}
 
-                match fs::rename(&src, &dest) {
-                    Ok(_) => {}
-                    Err(rename_err) => {
-                        fs::copy(&src, &dest).with_context(|| {
-                            format!(
-                                "Failed copying {} to {}: {}",
-                                src.display(),
-                                dest.display(),
-                                rename_err
-                            )
-                        })?;
-                        fs::remove_file(&src).with_context(|| {
-                            format!("Failed removing original file {}", src.display())
-                        })?;
-                    }
-                }
-            }
+impl FromStr for EntryOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "name" => Ok(EntryOrder::Name),
+            "size" => Ok(EntryOrder::Size),
+            "mtime" => Ok(EntryOrder::Mtime),
+            other => Err(anyhow!(
+                "Unknown entry order '{}'. Use name|size|mtime",
+                other
+            )),
         }
     }
+}
 
-    // If dry-run, print grouped output nicely
-    if dry_run {
-        print_dry_run_preview(&planned_moves);
+/// How a run reports its moves on stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// The dry-run tree and summary (the default).
+    #[default]
+    Text,
+    /// One `{"src":..,"dest":..}` object per line for each move, written as it is made (or,
+    /// in a dry run, as the plan is walked).
+    Jsonl,
+    /// In a dry run, aligned `folder`, `file` and `size` columns instead of the tree.
+    Table,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "table" => Ok(OutputFormat::Table),
+            other => Err(anyhow!("Unknown format '{}'. Use text|jsonl|table", other)),
+        }
     }
+}
 
-    Ok(())
+/// How the outcome of a real run is printed, on top of the usual output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// One JSON object with the counts, bytes, created folders and errors; see
+    /// `RunReport::to_json`.
+    Json,
 }
 
-/// Collect files matching `pattern` under `base`. If an existing folder with `prefix` exists
-/// under `base` we also collect matching files inside it (one-level) so we can `redo` distributions.
-fn collect_files(
-    base: &Path,
-    pattern: &str,
-    recursive: bool,
-    prefix: &str,
-) -> Result<Vec<PathBuf>> {
-    // Always canonicalize base first
-    let canonical_base = std::fs::canonicalize(base)
-        .with_context(|| format!("Failed to canonicalize {}", base.display()))?;
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
 
-    // Use string form — avoids internal strip_prefix panics in globwalk
-    let base_str = canonical_base
-        .to_str()
-        .ok_or_else(|| anyhow!("Base path is not valid UTF-8"))?
-        .to_string();
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(ReportFormat::Json),
+            other => Err(anyhow!("Unknown report format '{}'. Use json", other)),
+        }
+    }
+}
+
+/// How a real run carries out its moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MoveMode {
+    /// One move after another, in plan order (the default).
+    #[default]
+    Sequential,
+    /// Worker threads take moves from a queue while later folders are still being prepared.
+    /// Only the moves run in parallel: the walk and plan finish first, because where an
+    /// entry goes depends on all of them.
+    Parallel,
+}
 
-    // Build walker using the canonical absolute path string
-    let mut builder = GlobWalkerBuilder::from_patterns(&base_str, &[pattern]);
-    builder = builder.case_insensitive(true);
+impl FromStr for MoveMode {
+    type Err = anyhow::Error;
 
-    if recursive {
-        builder = builder.max_depth(usize::MAX);
-    } else {
-        builder = builder.max_depth(1);
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sequential" => Ok(MoveMode::Sequential),
+            "parallel" => Ok(MoveMode::Parallel),
+            other => Err(anyhow!(
+                "Unknown move mode '{}'. Use sequential|parallel",
+                other
+            )),
+        }
     }
+}
 
-    let walker = builder
-        .build()
-        .with_context(|| format!("Failed building glob walker for {}", base_str))?;
+/// How collected entries are grouped into target folders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GroupBy {
+    /// Split evenly by count into `subfolders` folders (the default).
+    #[default]
+    Count,
+    /// One folder per leading hex pair of each file's SHA-256, e.g. `group-ab`.
+    Hash,
+    /// One folder per lowercased file extension, e.g. `group-jpg`.
+    Extension,
+    /// One folder per immediate parent directory name, e.g. `group-album1` for
+    /// `artist/album1/track.mp3`.
+    Parent,
+}
 
-    let mut files: Vec<PathBuf> = walker
-        .filter_map(|entry| match entry {
-            Ok(e) => Some(e.path().to_path_buf()),
-            Err(err) => {
-                eprintln!("⚠️ Warning: skipping entry due to error: {}", err);
-                None
-            }
-        })
-        .filter(|p| p.is_file())
-        .collect();
+impl GroupBy {
+    /// Whether folders come from a per-entry key rather than a fixed count.
+    fn is_keyed(self) -> bool {
+        self != GroupBy::Count
+    }
+}
 
-    // Handle redo-existing prefix-* directories
-    if let Ok(readdir) = fs::read_dir(&canonical_base) {
-        for entry in readdir.filter_map(Result::ok) {
-            let s = entry.file_name().to_string_lossy().to_string();
-            if s.starts_with(prefix) && entry.path().is_dir() {
-                let inner_base = std::fs::canonicalize(entry.path()).with_context(|| {
-                    format!("Failed to canonicalize {}", entry.path().display())
-                })?;
-                let inner_str = inner_base
-                    .to_str()
-                    .ok_or_else(|| anyhow!("Invalid UTF-8 path"))?;
-                let inner_walker = GlobWalkerBuilder::from_patterns(inner_str, &[pattern])
-                    .max_depth(1)
-                    .build()
-                    .with_context(|| format!("Failed to build walker for {}", inner_str))?;
+impl FromStr for GroupBy {
+    type Err = anyhow::Error;
 
-                for e in inner_walker.filter_map(Result::ok) {
-                    let p = e.path().to_path_buf();
-                    if p.is_file() && !files.contains(&p) {
-                        files.push(p);
-                    }
-                }
-            }
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "count" => Ok(GroupBy::Count),
+            "hash" => Ok(GroupBy::Hash),
+            "extension" => Ok(GroupBy::Extension),
+            "parent" => Ok(GroupBy::Parent),
+            other => Err(anyhow!(
+                "Unknown grouping '{}'. Use count|hash|extension|parent",
+                other
+            )),
         }
     }
+}
 
-    files.sort();
-    Ok(files)
+/// What `run` does when nothing matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyMatch {
+    /// Print a note and return an empty report (the default).
+    #[default]
+    Ok,
+    /// Fail with `RefolderError::NoMatches`.
+    Error,
 }
 
-/// Partition `files` into `n` buckets as evenly as possible.
-/// If there are fewer files than buckets, some buckets will be empty.
-fn partition(files: Vec<PathBuf>, n: usize) -> Vec<Vec<PathBuf>> {
-    let total = files.len();
-    let mut buckets: Vec<Vec<PathBuf>> = vec![Vec::new(); n];
-    if n == 0 {
-        return buckets;
-    }
-    if total == 0 {
-        return buckets;
+impl FromStr for EmptyMatch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ok" => Ok(EmptyMatch::Ok),
+            "error" => Ok(EmptyMatch::Error),
+            other => Err(anyhow!(
+                "Unknown empty-match policy '{}'. Use ok|error",
+                other
+            )),
+        }
     }
+}
 
-    let base = total / n;
-    let rem = total % n;
+/// How `RunOptions::matching` is matched against entry names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchType {
+    /// Shell-style glob, handed to the directory walk (the default).
+    #[default]
+    Glob,
+    /// Regular expression searched for in the file name.
+    Regex,
+    /// The exact file name.
+    Literal,
+}
 
-    let mut idx = 0usize;
-    for i in 0..n {
-        let take = base + if i < rem { 1 } else { 0 };
-        for _ in 0..take {
-            if idx < files.len() {
-                buckets[i].push(files[idx].clone());
-                idx += 1;
-            }
+impl FromStr for MatchType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "glob" => Ok(MatchType::Glob),
+            "regex" => Ok(MatchType::Regex),
+            "literal" => Ok(MatchType::Literal),
+            other => Err(anyhow!(
+                "Unknown match type '{}'. Use glob|regex|literal",
+                other
+            )),
         }
     }
+}
 
-    buckets
+/// Errors callers may want to tell apart; reach them with `anyhow::Error::downcast_ref`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefolderError {
+    /// No entry matched, under `EmptyMatch::Error`.
+    NoMatches,
 }
 
-fn format_folder_name(prefix: &str, index: usize, suffix: &str) -> Result<String> {
-    match suffix {
-        "numbers" => Ok(format!("{}-{}", prefix, index)),
-        "letters" => {
-            // index to letters: 1 -> a, 2 -> b, ... 27 -> aa
-            let mut i = index;
-            let mut s = String::new();
-            while i > 0 {
-                i -= 1; // 0-based
-                let ch = ((i % 26) as u8 + b'a') as char;
-                s.insert(0, ch);
-                i /= 26;
-            }
-            Ok(format!("{}-{}", prefix, s))
+impl std::fmt::Display for RefolderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefolderError::NoMatches => write!(f, "No files matched pattern"),
         }
-        "none" => Ok(prefix.to_string()),
-        other => Err(anyhow!(
-            "Unknown suffix style '{}'. Use numbers|letters|none",
-            other
-        )),
     }
 }
 
-pub fn print_dry_run_preview(file_moves: &[(String, String)]) {
-    let mut folders: BTreeMap<String, Vec<String>> = BTreeMap::new();
+impl std::error::Error for RefolderError {}
 
-    for (_src, dst) in file_moves {
-        let dst_path = Path::new(dst);
-        let folder = dst_path
-            .parent()
-            .unwrap_or_else(|| Path::new("."))
-            .to_string_lossy()
-            .to_string();
-        let file_name = dst_path
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-        folders.entry(folder).or_default().push(file_name);
+/// How keyed groupings (`--by hash|extension|parent`) name their folders. Without it they are
+/// named `<prefix><separator><key>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FolderNameFrom {
+    /// The key alone, e.g. `jpg`.
+    Key,
+    /// Prefix and suffix style like `--by count`, numbered in key order.
+    Index,
+}
+
+impl FromStr for FolderNameFrom {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "key" => Ok(FolderNameFrom::Key),
+            "index" => Ok(FolderNameFrom::Index),
+            other => Err(anyhow!("Unknown folder naming '{}'. Use key|index", other)),
+        }
     }
+}
 
-    println!(".");
-    let folder_names: Vec<_> = folders.keys().cloned().collect();
-    let last_folder_idx = folder_names.len().saturating_sub(1);
+/// Key for `--by extension` files that have no extension.
+const NO_EXTENSION_KEY: &str = "no-extension";
 
-    for (i, folder) in folder_names.iter().enumerate() {
-        let is_last_folder = i == last_folder_idx;
-        let prefix_folder = if is_last_folder {
-            "└── "
-        } else {
-            "├── "
-        };
+/// Key for `--by parent` files that sit directly in the base directory.
+const NO_PARENT_KEY: &str = "root";
 
-        let folder_name = Path::new(folder)
-            .file_name()
-            .unwrap_or_else(|| std::ffi::OsStr::new(folder))
-            .to_string_lossy();
+/// Number of hex characters of the content hash used to name `--by hash` folders.
+const HASH_PREFIX_LEN: usize = 2;
 
-        // Wrap folder name in bold ANSI codes
-        println!("{}{}{}{}", prefix_folder, BOLD_START, folder_name, BOLD_END);
+/// Options for a single `run`. Mirrors the command line flags.
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// Pattern for matching entries, interpreted according to `match_type`.
+    pub matching: String,
+    /// Further glob patterns combined with `matching`; a `!`-prefixed one drops the entries
+    /// it matches.
+    pub extra_patterns: Vec<String>,
+    /// Whether `matching` is a glob, a regex or a literal file name.
+    pub match_type: MatchType,
+    /// Number of subfolders to split into.
+    pub subfolders: usize,
+    /// Create the target folders here instead of inside the base directory.
+    pub output_dir: Option<PathBuf>,
+    /// Nest every target folder under a parent named from the current UTC time with this
+    /// format (`%Y-%m-%dT%H-%M`), inside the base or `output_dir`.
+    pub timestamp_folder: Option<String>,
+    /// Derive the folder count so no folder holds more than this many entries.
+    pub max_per_folder: Option<usize>,
+    /// Derive the folder count so each folder holds about this percentage of the entries,
+    /// e.g. `25.0` for four folders.
+    pub folder_share: Option<f64>,
+    /// Refuse plans with more than this many folders.
+    pub max_folders: Option<usize>,
+    /// Refuse to run when fewer than this many entries match, e.g. against the wrong path.
+    pub min_files: Option<usize>,
+    /// Give every collected entry its own folder: `subfolders` becomes the entry count.
+    pub one_per_folder: bool,
+    /// When the count grouping ends up with a single folder, create none: entries stay in (or
+    /// move back to) the base path, or the output directory.
+    pub collapse_single: bool,
+    /// After a real run, remove empty `<prefix><separator><number>` folders the plan no longer
+    /// uses, such as those left by an earlier run with more folders.
+    pub replace_existing_folders: bool,
+    /// Prefix for created subfolders.
+    pub prefix: String,
+    /// Use the longest common file name prefix of the matched entries (trimmed of trailing
+    /// digits and separators) instead of `prefix`, falling back to `prefix` if there is none.
+    pub auto_prefix: bool,
+    /// How the bucket index is rendered after the prefix.
+    pub suffix: SuffixStyle,
+    /// Text between the prefix and the suffix.
+    pub separator: String,
+    /// Number given to the first folder.
+    pub start_index: usize,
+    /// Folder name template with `{prefix}`, `{index}` (the styled suffix) and `{count}`
+    /// (entries in that folder) placeholders; replaces prefix + separator + suffix.
+    pub template: Option<String>,
+    /// Explicit folder names, used in order instead of generated ones. Fewer labels than
+    /// folders is an error unless `cycle_labels` is set.
+    pub labels: Vec<String>,
+    /// Reuse `labels` with a round number once they run out.
+    pub cycle_labels: bool,
+    /// Existing folders (relative to the base or `output_dir`) to fill instead of generated
+    /// ones; the folder count is their number.
+    pub into: Vec<String>,
+    /// Create folders listed in `into` that do not exist rather than failing.
+    pub create_missing: bool,
+    /// On a redo, give each bucket the folder most of its entries already live in, so fewer
+    /// entries move. Only the bucket-to-folder pairing changes, never the buckets.
+    pub prefer_origin: bool,
+    /// Zero-padding of numeric suffixes: a fixed width, or derived from the folder count.
+    pub pad: Pad,
+    /// Recurse into subdirectories.
+    pub recursive: bool,
+    /// Print actions without performing them.
+    pub dry_run: bool,
+    /// Overwrite existing files/folders in destination.
+    pub force: bool,
+    /// Which kinds of entries to collect.
+    pub entries: Entries,
+    /// Treat any error during the directory walk as fatal instead of skipping the entry.
+    pub strict: bool,
+    /// Replace an existing destination only when the entry is newer than it; otherwise leave
+    /// the entry in place (counted as skipped).
+    pub update: bool,
+    /// Leave entries that cannot be moved because they are open or locked in place (counted as
+    /// skipped) instead of aborting the run.
+    pub skip_locked: bool,
+    /// Refuse plans with any destination outside the base (or `output_dir`).
+    pub strict_paths: bool,
+    /// Skip unreadable entries without a warning per entry; they are still counted in
+    /// `RunReport::walk_errors`.
+    pub quiet_errors: bool,
+    /// Whether a run that matches nothing succeeds or fails.
+    pub on_empty_match: EmptyMatch,
+    /// How entries are grouped into folders.
+    pub by: GroupBy,
+    /// Folder naming for keyed groupings; `None` keeps `<prefix><separator><key>`.
+    pub folder_name_from: Option<FolderNameFrom>,
+    /// Walk with this many threads. `None` keeps the single-threaded walk. Also the number
+    /// of movers under `MoveMode::Parallel`, where `None` means one per core.
+    pub threads: Option<usize>,
+    /// Move one entry at a time, or on a pool of worker threads.
+    pub move_mode: MoveMode,
+    /// Only collect entries with one of these extensions, on top of `matching`.
+    pub ext: Vec<String>,
+    /// Never collect entries whose name matches one of these globs, in the base or in
+    /// existing target folders.
+    pub exclude: Vec<String>,
+    /// Never descend into directories whose name matches one of these globs, e.g.
+    /// `node_modules`. Unlike `exclude`, the subtree is not walked at all.
+    pub prune_dir: Vec<String>,
+    /// Directory names (exact, not globs) that are never walked, collected or treated as
+    /// target folders, e.g. `_originals`.
+    pub exclude_dir_name: Vec<String>,
+    /// Never collect entries whose name starts with a dot.
+    pub skip_hidden: bool,
+    /// Record every entry's destination in this CSV file after a real run; `verify` reads it back.
+    pub manifest: Option<PathBuf>,
+    /// Add each moved file's SHA-256, hashed at its destination, to the manifest.
+    pub checksum_manifest: bool,
+    /// Append each real run (start time and moves) to this journal file.
+    pub journal: Option<PathBuf>,
+    /// Pin entries to folders with `<file name> -> <folder>` lines from this file. Listed
+    /// entries must all be matched; unlisted ones are distributed as usual.
+    pub mapping: Option<PathBuf>,
+    /// Group entries by the key this file gives their file name (`name,key` CSV rows or a
+    /// JSON object), one folder per key, named like the `by` groupings.
+    pub group_map: Option<PathBuf>,
+    /// Group key for entries missing from `group_map`; without it they are left in place
+    /// and counted as skipped.
+    pub group_map_default: Option<String>,
+    /// Only collect entries modified since the last run recorded in `journal`.
+    pub since_run: bool,
+    /// Only collect entries whose name does not appear anywhere under this reference
+    /// directory, so a sync-style run only distributes what the reference is missing.
+    pub mirror: Option<PathBuf>,
 
-        let mut files = folders.get(folder).unwrap().clone();
-        files.sort();
-        let last_file_idx = files.len().saturating_sub(1);
+    /// Case normalization for destination file names.
+    pub rename_case: RenameCase,
+    /// Destination file name template with `{stem}`, `{ext}`, `{index}` (position in the
+    /// folder, from 1) and `{folder}`; applied before `rename_case` and collision handling.
+    pub rename_template: Option<String>,
+    /// Unicode normalization for destination file names, applied before `rename_case`.
+    pub normalize_unicode: Option<UnicodeForm>,
+    /// How to resolve two entries (or an entry and an existing file) sharing a destination.
+    pub on_collision: OnCollision,
+    /// Make every destination file name unique across the whole plan, not just within its
+    /// folder, by adding the same `-N` counter as `OnCollision::Rename`.
+    pub unique_names: bool,
 
-        for (j, file) in files.into_iter().enumerate() {
-            let prefix_file = if j == last_file_idx {
-                if is_last_folder {
-                    "    └── "
-                } else {
-                    "│   └── "
-                }
-            } else {
-                if is_last_folder {
-                    "    ├── "
-                } else {
-                    "│   ├── "
-                }
-            };
-            println!("{}{}", prefix_file, file);
+    /// How entries are spread across the `subfolders` buckets.
+    pub strategy: Strategy,
+    /// Leave an entry where it is when its target folder already holds a file with the same
+    /// content, under any name; it is reported as skipped.
+    pub skip_identical_content: bool,
+    /// Move every entry the pattern does not match (but the other filters let through) into
+    /// a folder of this name, next to the distributed ones.
+    pub group_unmatched: Option<String>,
+    /// Byte ceiling per folder: entries that would take a folder past it spill into extra
+    /// folders after the requested ones.
+    pub max_bytes: Option<u64>,
+    /// Floor per folder, in entries or bytes: a folder below it is merged into the next one
+    /// (the last into the one before it). Only a lone folder may stay under it.
+    pub min_bucket_size: Option<MinBucket>,
+    /// Leading text ignored in file names when ordering entries, e.g. a camera's `DSC_`.
+    /// Destinations keep the full names.
+    pub strip_prefix: Option<String>,
+    /// Which buckets of a `Strategy::Count` split get the extra entries.
+    pub remainder: Remainder,
+    /// The order `Strategy::Size` places entries in.
+    pub fill: Fill,
+    /// Leave an existing distribution alone when every entry is already in one of the target
+    /// folders and no folder's count is off the even share by more than this percentage of
+    /// the average folder size.
+    pub move_threshold: Option<f64>,
+
+    /// Sort entries with Unicode collation instead of byte order.
+    pub collate: bool,
+    /// Regex whose first capture group (or whole match) is the sort key for each file name;
+    /// numeric keys sort numerically and unmatched names go last.
+    pub sort_key: Option<String>,
+
+    /// Draw the dry-run tree with ASCII characters only.
+    pub ascii: bool,
+    /// Print the plan as JSON to stderr before executing it (or previewing it on dry-run).
+    pub print_plan: bool,
+    /// Annotate each dry-run entry with why it went to its folder.
+    pub explain: bool,
+    /// Refuse to run if any entry would move to another filesystem, where the move becomes a
+    /// copy and delete.
+    pub same_filesystem_only: bool,
+    /// Fill each new folder under a hidden `.<name>.tmp` directory and rename it into place
+    /// once all its entries have moved.
+    pub stage: bool,
+    /// Before moving anything, fail if a target filesystem lacks room for the files that
+    /// have to be copied onto it.
+    pub check_space: bool,
+    /// Leave existing target folders as they are and number new folders after the highest
+    /// existing numeric suffix, instead of redistributing them.
+    pub continue_numbering: bool,
+    /// Print moves as the dry-run tree (`Text`) or as JSON Lines.
+    pub format: OutputFormat,
+    /// Order of the folders in the dry-run preview.
+    pub sort_folders_by: FolderOrder,
+    /// Order of the entries within each folder in the plan, manifest and preview; `None`
+    /// keeps the order they were assigned in.
+    pub sort_within_folder: Option<EntryOrder>,
+    /// List at most this many entries per folder in the dry-run preview.
+    pub preview_limit: Option<usize>,
+    /// Keep the dry-run preview (tree or table) in `RunReport::preview` instead of printing
+    /// it, e.g. to show it through a pager.
+    pub capture_preview: bool,
+    /// Show paths relative to the base in the preview, the JSON plan and the manifest and
+    /// journal files. Moves still use absolute paths.
+    pub relative_output: bool,
+
+    /// Use the logical absolute base path instead of canonicalizing it, so symlinked
+    /// prefixes are preserved in sources and destinations.
+    pub no_canonicalize: bool,
+    /// Match the glob and recognise existing target folders case-insensitively.
+    pub ignore_case: bool,
+    /// Checked before every move; once set, the run stops and leaves the remaining entries
+    /// where they are. `main` sets it on Ctrl-C.
+    pub cancel: Option<std::sync::Arc<std::sync::atomic::AtomicBool>>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            matching: "*".to_string(),
+            subfolders: 1,
+            output_dir: None,
+            timestamp_folder: None,
+            max_per_folder: None,
+            folder_share: None,
+            max_folders: None,
+            min_files: None,
+            one_per_folder: false,
+            collapse_single: false,
+            replace_existing_folders: false,
+            prefix: "group".to_string(),
+            auto_prefix: false,
+            extra_patterns: Vec::new(),
+            match_type: MatchType::Glob,
+            suffix: SuffixStyle::Numbers,
+            separator: "-".to_string(),
+            start_index: 1,
+            template: None,
+            labels: Vec::new(),
+            cycle_labels: false,
+            into: Vec::new(),
+            create_missing: false,
+            prefer_origin: false,
+            pad: Pad::default(),
+            recursive: false,
+            dry_run: false,
+            force: false,
+            entries: Entries::Files,
+            strict: false,
+            update: false,
+            skip_locked: false,
+            strict_paths: false,
+            quiet_errors: false,
+            on_empty_match: EmptyMatch::Ok,
+            by: GroupBy::Count,
+            folder_name_from: None,
+            threads: None,
+            move_mode: MoveMode::Sequential,
+            ext: Vec::new(),
+            exclude: Vec::new(),
+            prune_dir: Vec::new(),
+            exclude_dir_name: Vec::new(),
+            skip_hidden: false,
+            manifest: None,
+            checksum_manifest: false,
+            journal: None,
+            mapping: None,
+            group_map: None,
+            group_map_default: None,
+            since_run: false,
+            mirror: None,
+            rename_case: RenameCase::None,
+            rename_template: None,
+            normalize_unicode: None,
+            on_collision: OnCollision::Error,
+            unique_names: false,
+            strategy: Strategy::Count,
+            remainder: Remainder::First,
+            fill: Fill::LargestFirst,
+            strip_prefix: None,
+            max_bytes: None,
+            min_bucket_size: None,
+            group_unmatched: None,
+            skip_identical_content: false,
+            move_threshold: None,
+            collate: false,
+            sort_key: None,
+            ascii: false,
+            print_plan: false,
+            explain: false,
+            continue_numbering: false,
+            check_space: false,
+            stage: false,
+            same_filesystem_only: false,
+            format: OutputFormat::Text,
+            sort_folders_by: FolderOrder::Name,
+            sort_within_folder: None,
+            preview_limit: None,
+            capture_preview: false,
+            relative_output: false,
+            no_canonicalize: false,
+            ignore_case: true,
+            cancel: None,
         }
     }
+}
 
-    // Optional: summary
-    println!("\nSummary:");
-    println!("  Total folders: {}", folders.len());
-    let total_files: usize = folders.values().map(|v| v.len()).sum();
-    println!("  Total files:   {}", total_files);
-    println!("  Mode:          dry-run (no changes made)");
+/// A single entry and the destination it is planned to move to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedMove {
+    pub src: PathBuf,
+    pub dest: PathBuf,
+    /// Bytes `src` held when it was collected (for a directory, the total of the files
+    /// under it); zero when the move was read back from a manifest.
+    pub size: u64,
+    /// Modification time of `src` when it was collected, if known.
+    pub modified: Option<std::time::SystemTime>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs::{self, File};
-    use tempfile::tempdir;
+impl PlannedMove {
+    /// A move with no recorded size or modification time.
+    pub fn new(src: PathBuf, dest: PathBuf) -> PlannedMove {
+        PlannedMove {
+            src,
+            dest,
+            size: 0,
+            modified: None,
+        }
+    }
 
-    #[test]
-    fn test_partition_even() {
-        let files: Vec<PathBuf> = (0..8).map(|i| PathBuf::from(format!("f{}", i))).collect();
-        let buckets = partition(files, 4);
-        assert_eq!(buckets.len(), 4);
-        assert_eq!(
-            buckets.iter().map(|b| b.len()).collect::<Vec<_>>(),
-            vec![2, 2, 2, 2]
-        );
+    /// The entry is already where it should be (e.g. on a redo).
+    pub fn is_noop(&self) -> bool {
+        self.src == self.dest
     }
+}
 
-    #[test]
-    fn test_partition_uneven() {
-        let files: Vec<PathBuf> = (0..10).map(|i| PathBuf::from(format!("f{}", i))).collect();
-        let buckets = partition(files, 3);
-        assert_eq!(
-            buckets.iter().map(|b| b.len()).collect::<Vec<_>>(),
-            vec![4, 3, 3]
-        );
+/// A target folder and the entries planned to land in it.
+#[derive(Debug, Clone)]
+pub struct PlannedFolder {
+    pub path: PathBuf,
+    pub moves: Vec<PlannedMove>,
+}
+
+/// Everything a `run` would do, computed without touching the filesystem.
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    /// Target folders in creation order.
+    pub folders: Vec<PlannedFolder>,
+    /// Errors for entries that were skipped during the directory walk.
+    pub walk_errors: Vec<String>,
+    /// Entries left in place because of a destination collision (`--on-collision skip`), or
+    /// because `group_map` has no key for them.
+    pub skipped: Vec<PathBuf>,
+    /// Number of entries collected, whether or not they have to move.
+    pub matched: usize,
+    /// The existing folders are within `move_threshold` of an even split, so nothing moves.
+    pub already_balanced: bool,
+    /// Why each entry goes where it does, keyed by source; only filled under `explain`.
+    pub reasons: BTreeMap<PathBuf, String>,
+}
+
+impl Plan {
+    /// All planned moves, folder by folder.
+    pub fn moves(&self) -> impl Iterator<Item = &PlannedMove> {
+        self.folders.iter().flat_map(|f| f.moves.iter())
     }
 
-    #[test]
-    fn test_format_folder_name_letters() {
-        assert_eq!(format_folder_name("ex", 1, "letters").unwrap(), "ex-a");
-        assert_eq!(format_folder_name("ex", 26, "letters").unwrap(), "ex-z");
-        assert_eq!(format_folder_name("ex", 27, "letters").unwrap(), "ex-aa");
+    /// The plan as a single-line JSON object: folders with their moves, plus skipped entries.
+    pub fn to_json(&self) -> String {
+        json::plan(self)
     }
 
-    #[test]
-    fn integration_move_files() -> Result<()> {
-        let dir = tempdir()?;
-        let base = dir.path();
-        // create 5 files
-        for i in 0..5 {
-            let p = base.join(format!("file{}.txt", i));
-            File::create(&p)?;
+    /// The same plan with every path under `base` made relative to it, for output that should
+    /// not depend on where the tree lives. Relative paths are joined with `/` on every
+    /// platform, so the same tree gives byte-identical manifests everywhere. Paths outside
+    /// `base` are kept as they are.
+    pub fn relative_to(&self, base: &Path) -> Plan {
+        let rel = |p: &Path| match p.strip_prefix(base) {
+            Ok(rel) if std::path::MAIN_SEPARATOR != '/' => PathBuf::from(
+                rel.components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+            ),
+            Ok(rel) => rel.to_path_buf(),
+            Err(_) => p.to_path_buf(),
+        };
+        Plan {
+            folders: self
+                .folders
+                .iter()
+                .map(|f| PlannedFolder {
+                    path: rel(&f.path),
+                    moves: f
+                        .moves
+                        .iter()
+                        .map(|m| PlannedMove {
+                            src: rel(&m.src),
+                            dest: rel(&m.dest),
+                            ..m.clone()
+                        })
+                        .collect(),
+                })
+                .collect(),
+            skipped: self.skipped.iter().map(|p| rel(p)).collect(),
+            reasons: self
+                .reasons
+                .iter()
+                .map(|(src, reason)| (rel(src), reason.clone()))
+                .collect(),
+            ..self.clone()
         }
+    }
 
-        // run - move into 3 buckets, force=true so that existing won't block (not needed here)
-        run(
-            base.to_str().unwrap(),
-            "*.txt",
-            3,
-            "pack",
-            "numbers",
-            false,
-            false,
-            true,
-        )?;
+    /// `(source, destination)` display pairs, as taken by `print_dry_run_preview`.
+    pub fn display_pairs(&self) -> Vec<(String, String)> {
+        self.moves()
+            .map(|m| (m.src.display().to_string(), m.dest.display().to_string()))
+            .collect()
+    }
 
-        // check folders
-        let a = base.join("pack-1");
-        let b = base.join("pack-2");
-        let c = base.join("pack-3");
-        assert!(a.is_dir() && b.is_dir() && c.is_dir());
+    /// `reasons` keyed by the displayed source, as taken by the preview under `explain`.
+    fn display_reasons(&self) -> BTreeMap<String, String> {
+        self.reasons
+            .iter()
+            .map(|(src, reason)| (src.display().to_string(), reason.clone()))
+            .collect()
+    }
+}
 
-        let cnts = vec![
-            fs::read_dir(&a)?.count(),
-            fs::read_dir(&b)?.count(),
-            fs::read_dir(&c)?.count(),
-        ];
-        // distribution should sum to 5
-        assert_eq!(cnts.iter().sum::<usize>(), 5);
+/// Outcome of a `run`.
+#[derive(Debug, Clone, Default)]
+pub struct RunReport {
+    /// Number of entries collected. Zero means nothing matched.
+    pub matched: usize,
+    /// Number of target folders the entries were distributed into.
+    pub folders: usize,
+    /// Target folders this run created (always empty on dry-run).
+    pub created: Vec<PathBuf>,
+    /// Number of entries moved (or that would be moved, on dry-run).
+    pub moved: usize,
+    /// Number of entries left in place because of a destination collision, because they
+    /// were locked under `skip_locked`, or because they were not newer under `update`.
+    pub skipped: usize,
+    /// Total size of the entries actually moved (always zero on dry-run).
+    pub bytes_moved: u64,
+    /// Errors for entries that were skipped during the directory walk.
+    pub walk_errors: Vec<String>,
+    /// Per source folder, how many entries went to each target folder (no-ops included).
+    /// Entries that came straight from the base directory are keyed `"."`.
+    pub by_origin: BTreeMap<String, BTreeMap<String, usize>>,
+    /// Base directories that failed under `run_all`, as `"<path>: <error>"`.
+    pub base_errors: Vec<String>,
+    /// The run was stopped through `RunOptions::cancel`; unmoved entries count as skipped.
+    pub cancelled: bool,
+    /// The dry-run preview, under `RunOptions::capture_preview`; empty otherwise.
+    pub preview: String,
+}
 
-        Ok(())
+impl RunReport {
+    /// Some entries were left behind: skipped at planning or move time, or unreadable
+    /// during the walk.
+    pub fn is_partial(&self) -> bool {
+        self.skipped > 0 || !self.walk_errors.is_empty()
     }
 
-    #[test]
-    fn redo_existing_folders() -> Result<()> {
-        // Test that files inside existing prefix-* folders are collected and re-shuffled
-        let dir = tempdir()?;
-        let base = dir.path();
+    /// Fold `other` into this report, summing counts.
+    pub fn merge(&mut self, other: RunReport) {
+        self.matched += other.matched;
+        self.folders += other.folders;
+        self.created.extend(other.created);
+        self.preview.push_str(&other.preview);
+        self.moved += other.moved;
+        self.skipped += other.skipped;
+        self.bytes_moved += other.bytes_moved;
+        self.walk_errors.extend(other.walk_errors);
+        for (origin, targets) in other.by_origin {
+            let merged = self.by_origin.entry(origin).or_default();
+            for (target, n) in targets {
+                *merged.entry(target).or_default() += n;
+            }
+        }
+        self.base_errors.extend(other.base_errors);
+        self.cancelled |= other.cancelled;
+    }
 
-        // create two existing folders pack-1 and pack-2 with some files
-        let p1 = base.join("pack-1");
-        let p2 = base.join("pack-2");
-        fs::create_dir_all(&p1)?;
-        fs::create_dir_all(&p2)?;
+    /// The report as a single-line JSON object, e.g. for `--report json`:
+    /// `{"matched":..,"folders":..,"created":[..],"moved":..,"skipped":..,"unreadable":..,
+    /// "bytes_moved":..,"cancelled":..,"errors":[..],"base_errors":[..]}`. `unreadable`
+    /// counts the entries in `errors`, those that could not be read during the walk.
+    pub fn to_json(&self) -> String {
+        json::report(self)
+    }
+}
 
-        File::create(p1.join("a.txt"))?;
-        File::create(p1.join("b.txt"))?;
-        File::create(p2.join("c.txt"))?;
+/// Name of the folder `src` currently lives in, relative to `base`: `"."` for the base itself,
+/// otherwise the parent directory's name (e.g. `pack-2` on a redo).
+fn origin_label(src: &Path, base: &Path) -> String {
+    match src.parent() {
+        Some(parent) if parent != base => parent
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| ".".to_string()),
+        _ => ".".to_string(),
+    }
+}
 
-        // Now ask to re-split into 3 buckets
-        run(
-            base.to_str().unwrap(),
-            "*.txt",
-            3,
-            "pack",
-            "numbers",
-            false,
-            false,
-            true,
-        )?;
+/// Public API: run the refolder operation.
+pub fn run(base_path: &str, opts: &RunOptions) -> Result<RunReport> {
+    run_with_progress(base_path, opts, |_| {})
+}
 
-        // ensure pack-1..pack-3 exist and files moved
-        let p3 = base.join("pack-3");
-        assert!(p1.is_dir() && p2.is_dir() && p3.is_dir());
-        let total: usize = [p1, p2, p3]
-            .iter()
-            .map(|d| fs::read_dir(d).unwrap().count())
-            .sum();
-        assert_eq!(total, 3);
+/// `run`, calling `on_move` after every entry that was actually moved.
+///
+/// If `opts.cancel` is set while the moves are under way, the run stops before the next move:
+/// the rest stay in place, the report is marked `cancelled`, and no manifest is written. The
+/// journal keeps the moves that were made, so `resume` can finish the run later.
+pub fn run_with_progress(
+    base_path: &str,
+    opts: &RunOptions,
+    on_move: impl FnMut(&PlannedMove),
+) -> Result<RunReport> {
+    // Taken before collecting, so files arriving during the run count as new next time
+    let started = std::time::SystemTime::now();
+    let plan = plan(base_path, opts)?;
+    execute(base_path, opts, plan, started, on_move)
+}
 
-        Ok(())
+/// Finish an interrupted run: carry out the moves the last run in `opts.journal` planned but
+/// did not record as done. The plan comes from the journal rather than being recomputed,
+/// since a half-moved tree would plan differently. Entries that are gone by now are skipped
+/// with a warning.
+pub fn resume(base_path: &str, opts: &RunOptions) -> Result<RunReport> {
+    let journal_path = opts
+        .journal
+        .as_ref()
+        .ok_or_else(|| anyhow!("resume needs --journal"))?;
+    let started = std::time::SystemTime::now();
+    let pending = journal::pending(journal_path)?;
+    if pending.is_empty() {
+        info!(
+            "Nothing to resume: the last run in {} finished.",
+            journal_path.display()
+        );
+        return Ok(RunReport::default());
     }
 
-    #[test]
-    fn test_strip_prefix_safe() -> Result<()> {
-        // Use "." explicitly to simulate the common cause of StripPrefixError
-        let dir = tempdir()?;
-        let base = dir.path();
+    // Relative journal paths (`--relative-output`) are resolved against the base again
+    let base = resolve_base(Path::new(base_path), opts)?;
+    let mut folders: Vec<PlannedFolder> = Vec::new();
+    for m in &pending {
+        // Never collected, so this is the one stat these entries get
+        let src = base.join(&m.src);
+        let entry = CollectedFile::stat(src.clone());
+        let m = collected_move(src, base.join(&m.dest), entry.as_ref());
+        let path = m.dest.parent().unwrap_or(&base).to_path_buf();
+        match folders.iter_mut().find(|f| f.path == path) {
+            Some(folder) => folder.moves.push(m),
+            None => folders.push(PlannedFolder {
+                path,
+                moves: vec![m],
+            }),
+        }
+    }
+    let plan = Plan {
+        folders,
+        matched: pending.len(),
+        ..Default::default()
+    };
+    execute(base_path, opts, plan, started, |_| {})
+}
+
+/// Carry out `plan` for a run over `base_path` that started at `started`: preflight checks,
+/// the moves themselves (or the dry-run preview), then the manifest and journal.
+fn execute(
+    base_path: &str,
+    opts: &RunOptions,
+    plan: Plan,
+    started: std::time::SystemTime,
+    mut on_move: impl FnMut(&PlannedMove),
+) -> Result<RunReport> {
+    let mut report = RunReport {
+        matched: plan.matched,
+        walk_errors: plan.walk_errors.clone(),
+        skipped: plan.skipped.len(),
+        ..Default::default()
+    };
+    // Sources are resolved the same way, so this is what origins are relative to
+    let source_base = resolve_base(Path::new(base_path), opts)?;
+    // What the preview, JSON and manifests show; the moves themselves use `plan`
+    let shown = if opts.relative_output {
+        plan.relative_to(&source_base)
+    } else {
+        plan.clone()
+    };
+
+    if opts.print_plan {
+        eprintln!("{}", shown.to_json());
+    }
+
+    if plan.already_balanced {
+        info!("Already balanced, nothing to do.");
+        return Ok(report);
+    }
+    // Entries can match and still all be left in place, e.g. missing from `group_map`, and
+    // `group_unmatched` can sweep entries when the pattern matched none
+    if plan.matched == 0 && plan.folders.is_empty() {
+        if opts.on_empty_match == EmptyMatch::Error {
+            return Err(RefolderError::NoMatches.into());
+        }
+        info!("No files matched pattern. Nothing to do.");
+        return Ok(report);
+    }
+    if opts.same_filesystem_only {
+        space::check_same_filesystem(&plan)?;
+    }
+    if opts.check_space {
+        space::check(&plan)?;
+    }
+    if !opts.dry_run {
+        space::check_writable(&plan)?;
+    }
+
+    // Folders still to be created; the ones that exist after the moves were made by this run
+    let new_folders: Vec<&Path> = if opts.dry_run {
+        Vec::new()
+    } else {
+        plan.folders
+            .iter()
+            .map(|f| f.path.as_path())
+            .filter(|p| !p.exists())
+            .collect()
+    };
+
+    // Written as the moves happen, so an interrupted run can be resumed
+    let mut journal = match &opts.journal {
+        Some(path) if !opts.dry_run => Some(journal::Writer::begin(path, started, &shown)?),
+        _ => None,
+    };
+
+    // (folder, move) indices of the entries that actually moved
+    let mut moved: HashSet<(usize, usize)> = HashSet::new();
+    // Parallel moves all happen up front; the loop below then only does the bookkeeping
+    let parallel = match opts.move_mode {
+        MoveMode::Parallel if !opts.dry_run => Some(move_parallel(
+            &plan,
+            &shown,
+            opts,
+            &mut report,
+            &mut journal,
+            &mut moved,
+            &mut on_move,
+        )?),
+        _ => None,
+    };
+
+    for (i, (folder, shown_folder)) in plan.folders.iter().zip(&shown.folders).enumerate() {
+        // Entries left where they are at move time (locked, or not newer under `update`)
+        let mut left = 0;
+        if let Some(parallel) = &parallel {
+            left = parallel[i];
+        } else if !opts.dry_run {
+            // If not dry-run, perform actual creation and moving
+            let staging = prepare_folder(folder, opts)?;
+
+            for (j, (m, shown_move)) in folder.moves.iter().zip(&shown_folder.moves).enumerate() {
+                // Skip identical (redo safe)
+                if m.is_noop() {
+                    continue;
+                }
+                if is_cancelled(opts) {
+                    report.cancelled = true;
+                    left += 1;
+                    continue;
+                }
+                let dest = match &staging {
+                    Some(staging) => staging.join(m.dest.strip_prefix(&folder.path)?),
+                    None => m.dest.clone(),
+                };
+                match move_one(m, &dest, opts)? {
+                    Some(bytes) => {
+                        if let Some(journal) = &mut journal {
+                            journal.record(shown_move)?;
+                        }
+                        if opts.format == OutputFormat::Jsonl {
+                            println!("{}", json::move_object(shown_move));
+                        }
+                        report.bytes_moved += bytes;
+                        moved.insert((i, j));
+                        on_move(m);
+                    }
+                    None => left += 1,
+                }
+            }
+            if let Some(staging) = staging.as_ref().filter(|s| s.exists()) {
+                fs::rename(staging, &folder.path).with_context(|| {
+                    format!(
+                        "Failed renaming {} to {}",
+                        staging.display(),
+                        folder.path.display()
+                    )
+                })?;
+            }
+        }
+
+        report.folders += 1;
+        report.moved += folder.moves.iter().filter(|m| !m.is_noop()).count() - left;
+        report.skipped += left;
+
+        let target = folder
+            .path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        for m in &folder.moves {
+            *report
+                .by_origin
+                .entry(origin_label(&m.src, &source_base))
+                .or_default()
+                .entry(target.clone())
+                .or_default() += 1;
+        }
+    }
+
+    report.created = new_folders
+        .into_iter()
+        .filter(|p| p.exists())
+        .map(Path::to_path_buf)
+        .collect();
+
+    // If dry-run, print grouped output nicely
+    if opts.dry_run && opts.format == OutputFormat::Jsonl {
+        write_jsonl(&mut std::io::stdout().lock(), &shown)?;
+    } else if opts.dry_run && opts.format == OutputFormat::Table {
+        let rows: Vec<TableRow> = plan
+            .folders
+            .iter()
+            .zip(&shown.folders)
+            .flat_map(|(folder, shown_folder)| folder.moves.iter().zip(&shown_folder.moves))
+            .map(|(m, shown_move)| TableRow {
+                folder: shown_move
+                    .dest
+                    .parent()
+                    .and_then(Path::file_name)
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+                file: shown_move
+                    .dest
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+                size: m.size,
+            })
+            .collect();
+        show_preview(render_table(&rows), opts, &mut report);
+    } else if opts.dry_run {
+        let theme = if opts.ascii {
+            Theme::ascii()
+        } else {
+            Theme::unicode()
+        };
+        let preview = PreviewOptions {
+            reasons: if opts.explain {
+                shown.display_reasons()
+            } else {
+                BTreeMap::new()
+            },
+            sizes: if opts.sort_folders_by == FolderOrder::Size {
+                plan.moves()
+                    .zip(shown.moves())
+                    .map(|(m, shown_move)| (shown_move.src.display().to_string(), m.size))
+                    .collect()
+            } else {
+                BTreeMap::new()
+            },
+            by_origin: report.by_origin.clone(),
+            limit: opts.preview_limit,
+            order: opts.sort_folders_by,
+            keep_order: opts.sort_within_folder.is_some(),
+        };
+        show_preview(
+            render_dry_run_preview(&shown.display_pairs(), &preview, &theme),
+            opts,
+            &mut report,
+        );
+    } else if report.cancelled {
+        warn!(
+            "Cancelled: {} entries left in place, no manifest written",
+            report.skipped
+        );
+    } else {
+        if opts.replace_existing_folders {
+            remove_stale_folders(Path::new(base_path), opts, &plan)?;
+        }
+        if let Some(manifest_path) = &opts.manifest {
+            if opts.checksum_manifest {
+                // Hashed at the destination, so the digest is of what actually arrived. An
+                // entry left in place gets none: whatever sits at its destination is not it.
+                let digests = plan
+                    .folders
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(i, folder)| {
+                        folder.moves.iter().enumerate().map(move |(j, m)| (i, j, m))
+                    })
+                    .map(|(i, j, m)| {
+                        let arrived = m.is_noop() || moved.contains(&(i, j));
+                        match arrived && m.dest.is_file() {
+                            true => hash::sha256_hex(&m.dest),
+                            false => Ok(String::new()),
+                        }
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                manifest::write_checksummed(manifest_path, &shown, &digests)?;
+            } else {
+                manifest::write(manifest_path, &shown)?;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Run over several base directories with the same options, combining their reports.
+///
+/// Each base is distributed independently. A base that fails is recorded in
+/// `RunReport::base_errors` and the remaining bases still run, unless `opts.strict` is set,
+/// in which case the first failure is returned. A single base's failure is always returned.
+pub fn run_all<S: AsRef<str>>(base_paths: &[S], opts: &RunOptions) -> Result<RunReport> {
+    if base_paths.len() > 1 && opts.manifest.is_some() {
+        return Err(anyhow!("--manifest supports a single base path"));
+    }
+
+    let mut combined = RunReport::default();
+    for base_path in base_paths {
+        let base_path = base_path.as_ref();
+        match run(base_path, opts) {
+            Ok(report) => combined.merge(report),
+            Err(err) if opts.strict || base_paths.len() == 1 => {
+                return Err(err.context(base_path.to_string()));
+            }
+            Err(err) => combined
+                .base_errors
+                .push(format!("{}: {:#}", base_path, err)),
+        }
+    }
+    Ok(combined)
+}
+
+/// Compute the plan for `base_path` without moving anything: collect entries, partition
+/// them into buckets and name each bucket's folder.
+///
+/// `base_path` may be relative (`.`, `./sub`); it is resolved against the current directory
+/// up front, so every source and destination in the plan is absolute.
+pub fn plan(base_path: &str, opts: &RunOptions) -> Result<Plan> {
+    plan_with(base_path, opts, &CollectedFile::stat)
+}
+
+/// `plan` with `stat` reading each collected entry's metadata. Everything after the
+/// collection works from what `stat` returned.
+fn plan_with(
+    base_path: &str,
+    opts: &RunOptions,
+    stat: &dyn Fn(PathBuf) -> Option<CollectedFile>,
+) -> Result<Plan> {
+    if opts.subfolders == 0 {
+        return Err(anyhow!("subfolders must be greater than zero"));
+    }
+    // Merging up to the floor could take a folder back past the ceiling
+    if opts.min_bucket_size.is_some() && opts.max_bytes.is_some() {
+        return Err(anyhow!(
+            "--min-bucket-size cannot be combined with --max-bytes"
+        ));
+    }
+
+    let base = Path::new(base_path);
+    if !base.exists() {
+        return Err(anyhow!("Path '{}' does not exist", base.display()));
+    }
+    if !base.is_dir() {
+        return Err(anyhow!("Path '{}' is not a directory", base.display()));
+    }
+
+    // The derived prefix decides which folders are redo sources, so it has to be known
+    // before the real collection: derive it from a first pass, then collect again.
+    let derived;
+    let opts = if opts.auto_prefix {
+        let first = collect_files_with(base, opts, stat)?;
+        derived = RunOptions {
+            prefix: common_name_prefix(&first.files).unwrap_or_else(|| opts.prefix.clone()),
+            auto_prefix: false,
+            ..opts.clone()
+        };
+        &derived
+    } else {
+        opts
+    };
+
+    // Existing folders are left alone and new ones are numbered after the highest of them
+    let continued;
+    let opts = if opts.continue_numbering {
+        let target_base = opts.output_dir.as_deref().unwrap_or(base);
+        continued = RunOptions {
+            start_index: highest_suffix(target_base, opts)?
+                .map_or(opts.start_index, |highest| highest + 1),
+            ..opts.clone()
+        };
+        &continued
+    } else {
+        opts
+    };
+
+    // 1) Collect entries to operate on. If they live under existing target folders (prefix-<i>),
+    // treat them as sources as well so we can "redo" distributions.
+    let Collected { files, walk_errors } = collect_files_with(base, opts, stat)?;
+
+    if let Some(min) = opts.min_files
+        && files.len() < min
+    {
+        return Err(anyhow!(
+            "Only {} entries matched, fewer than --min-files {}",
+            files.len(),
+            min
+        ));
+    }
+
+    if opts.folder_name_from.is_some() && !opts.by.is_keyed() && opts.group_map.is_none() {
+        return Err(anyhow!(
+            "--folder-name-from needs a keyed grouping (--by hash|extension|parent or --group-map)"
+        ));
+    }
+    if opts.group_map.is_some() && (opts.by.is_keyed() || !opts.into.is_empty()) {
+        return Err(anyhow!(
+            "--group-map gives the folders; it cannot be combined with --by or --into"
+        ));
+    }
+
+    let mut sources: HashSet<PathBuf> = files.iter().map(|f| f.path.clone()).collect();
+    let matched = files.len();
+    // What the collection stat saw, carried into each move so nothing is stat'ed again
+    let mut stats: HashMap<PathBuf, CollectedFile> =
+        files.iter().map(|f| (f.path.clone(), f.clone())).collect();
+
+    // Whatever the other filters let through but the pattern did not match is swept into
+    // one folder
+    let unmatched: Vec<PathBuf> = match &opts.group_unmatched {
+        Some(_) => {
+            let everything = RunOptions {
+                matching: "*".into(),
+                extra_patterns: Vec::new(),
+                match_type: MatchType::Glob,
+                ext: Vec::new(),
+                ..opts.clone()
+            };
+            collect_files_with(base, &everything, stat)?
+                .files
+                .into_iter()
+                .filter(|f| !sources.contains(&f.path))
+                .map(|f| {
+                    let path = f.path.clone();
+                    stats.insert(f.path.clone(), f);
+                    path
+                })
+                .collect()
+        }
+        None => Vec::new(),
+    };
+    sources.extend(unmatched.iter().cloned());
+
+    // Nothing to distribute and nothing to sweep
+    if files.is_empty() && unmatched.is_empty() {
+        return Ok(Plan {
+            walk_errors,
+            ..Default::default()
+        });
+    }
+
+    // Pinned entries go to their mapped folders; only the rest is distributed
+    let (pinned, files) = match &opts.mapping {
+        Some(path) => mapping::apply(files, &mapping::read(path)?)?,
+        None => (Vec::new(), files),
+    };
+    let mut pinned: Vec<(String, Vec<PathBuf>)> = pinned
+        .into_iter()
+        .map(|(folder, entries)| (folder, entries.into_iter().map(|f| f.path).collect()))
+        .collect();
+    // Sizes come from the collection stat, in the same order as `files`
+    let (files, sizes): (Vec<PathBuf>, Vec<u64>) =
+        files.into_iter().map(|f| (f.path, f.size)).unzip();
+
+    // Where the target folders go. Sources are canonical, so destinations must be too for
+    // redo no-ops to be recognised.
+    let canonical_base = match &opts.output_dir {
+        Some(dir) if dir.exists() => resolve_base(dir, opts)?,
+        Some(dir) => std::path::absolute(dir)
+            .with_context(|| format!("Failed to resolve output directory {}", dir.display()))?,
+        None => resolve_base(base, opts)?,
+    };
+    let canonical_base = match &opts.timestamp_folder {
+        Some(format) => canonical_base.join(naming::format_timestamp(
+            format,
+            std::time::SystemTime::now(),
+        )?),
+        None => canonical_base,
+    };
+
+    if !opts.into.is_empty() {
+        if opts.by.is_keyed() || opts.one_per_folder || opts.max_per_folder.is_some() {
+            return Err(anyhow!(
+                "--into fixes the folders; it cannot be combined with --by, --one-per-folder or --max-per-folder"
+            ));
+        }
+        if !opts.create_missing {
+            let missing: Vec<&str> = opts
+                .into
+                .iter()
+                .filter(|dir| !canonical_base.join(dir).is_dir())
+                .map(String::as_str)
+                .collect();
+            if !missing.is_empty() {
+                return Err(anyhow!(
+                    "--into folders do not exist: {} (use --create-missing to create them)",
+                    missing.join(", ")
+                ));
+            }
+        }
+    }
+
+    let mut reasons: BTreeMap<PathBuf, String> = BTreeMap::new();
+    if opts.explain {
+        for (folder, entries) in &pinned {
+            for src in entries {
+                reasons.insert(src.clone(), format!("mapping → {}", folder));
+            }
+        }
+    }
+    if let Some(folder) = &opts.group_unmatched
+        && !unmatched.is_empty()
+    {
+        if opts.explain {
+            for src in &unmatched {
+                reasons.insert(src.clone(), format!("unmatched → {}", folder));
+            }
+        }
+        pinned.push((folder.clone(), unmatched));
+    }
+
+    // 2) Partition into named buckets
+    let mut unmapped: Vec<PathBuf> = Vec::new();
+    let buckets: Vec<(String, Vec<PathBuf>)> = match opts.by {
+        // Only swept entries: there is nothing to split
+        _ if files.is_empty() => Vec::new(),
+        GroupBy::Count if opts.group_map.is_some() => {
+            let keys = mapping::read_groups(opts.group_map.as_deref().expect("checked above"))?;
+            let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+            for src in files {
+                let name = src.file_name().unwrap_or_default().to_string_lossy();
+                match keys.get(name.as_ref()).or(opts.group_map_default.as_ref()) {
+                    Some(key) => {
+                        if opts.explain {
+                            reasons.insert(src.clone(), format!("group-map={}", key));
+                        }
+                        groups.entry(key.clone()).or_default().push(src);
+                    }
+                    None => unmapped.push(src),
+                }
+            }
+            name_keyed_groups(groups.into_iter().collect(), opts)?
+        }
+        GroupBy::Count => {
+            // `subfolders >= files` already yields one entry per folder (plus empty extras, which
+            // are never created);
+            // --one-per-folder sizes the count to match exactly.
+            let n = if !opts.into.is_empty() {
+                opts.into.len()
+            } else if opts.one_per_folder {
+                files.len()
+            } else if let Some(max) = opts.max_per_folder {
+                if max == 0 {
+                    return Err(anyhow!("--max-per-folder must be greater than zero"));
+                }
+                files.len().div_ceil(max)
+            } else if let Some(pct) = opts.folder_share {
+                if !(pct > 0.0 && pct <= 100.0) {
+                    return Err(anyhow!(
+                        "--subfolders percentage must be above 0 and up to 100"
+                    ));
+                }
+                let per_folder = ((files.len() as f64 * pct / 100.0).ceil() as usize).max(1);
+                files.len().div_ceil(per_folder)
+            } else {
+                opts.subfolders
+            };
+            // Every bucket would share the bare prefix and pile into one folder
+            if opts.suffix == SuffixStyle::None
+                && opts.template.is_none()
+                && opts.labels.is_empty()
+                && opts.into.is_empty()
+                && n > 1
+            {
+                return Err(anyhow!(
+                    "--suffix none names every folder '{}'; it needs exactly one folder, got {}",
+                    opts.prefix,
+                    n
+                ));
+            }
+            if opts.explain {
+                reasons.extend(files.iter().cloned().zip(assign::reasons(
+                    &sizes,
+                    n,
+                    opts.strategy,
+                    opts.remainder,
+                    opts.fill,
+                )));
+            }
+            let size_of: HashMap<PathBuf, u64> = match (opts.max_bytes, opts.min_bucket_size) {
+                (Some(_), _) | (_, Some(MinBucket::Bytes(_))) => {
+                    files.iter().cloned().zip(sizes.iter().copied()).collect()
+                }
+                _ => HashMap::new(),
+            };
+            let mut assigned =
+                assign::assign_sized(files, &sizes, n, opts.strategy, opts.remainder, opts.fill);
+            if let Some(max_bytes) = opts.max_bytes {
+                assigned = assign::cap_bytes(assigned, |f| size_of[f], max_bytes);
+                if opts.explain {
+                    for (bucket, entries) in assigned.range(n..) {
+                        for entry in entries {
+                            reasons.insert(
+                                entry.clone(),
+                                format!("bucket {} (spilled over --max-bytes)", bucket),
+                            );
+                        }
+                    }
+                }
+            }
+            if let Some(min) = opts.min_bucket_size {
+                let before: HashMap<PathBuf, usize> = if opts.explain {
+                    assigned
+                        .iter()
+                        .flat_map(|(&bucket, entries)| {
+                            entries.iter().map(move |e| (e.clone(), bucket))
+                        })
+                        .collect()
+                } else {
+                    HashMap::new()
+                };
+                assigned =
+                    assign::merge_small(assigned, |f| size_of.get(f).copied().unwrap_or(0), min);
+                for (bucket, entries) in &assigned {
+                    for entry in entries
+                        .iter()
+                        .filter(|e| before.get(*e).is_some_and(|b| b != bucket))
+                    {
+                        reasons.insert(
+                            entry.clone(),
+                            format!("bucket {} (merged up to --min-bucket-size)", bucket),
+                        );
+                    }
+                }
+            }
+            // Spilling may add folders, merging may remove them
+            let n = assigned.len();
+            if !opts.into.is_empty() && n > opts.into.len() {
+                return Err(anyhow!(
+                    "--max-bytes needs {} folders but --into lists {}",
+                    n,
+                    opts.into.len()
+                ));
+            }
+            // Named after assignment: a template may include each bucket's `{count}`
+            let pad = opts
+                .pad
+                .width(opts.start_index + n.saturating_sub(1), opts.suffix);
+            let slot_name = |i: usize, count: usize| -> Result<String> {
+                // A lone bucket is the base itself: entries stay, redo folders are emptied
+                if let Some(dir) = opts.into.get(i) {
+                    Ok(dir.clone())
+                } else if opts.collapse_single && n == 1 {
+                    Ok(String::new())
+                } else {
+                    bucket_name(opts, i, pad, count)
+                }
+            };
+            let mut assigned: Vec<Vec<PathBuf>> = assigned.into_values().collect();
+            if opts.prefer_origin {
+                let order = assign::match_origins(&assigned, |slot, bucket| {
+                    slot_name(slot, bucket.len()).map_or(0, |name| {
+                        let dir = canonical_base.join(name);
+                        bucket
+                            .iter()
+                            .filter(|f| f.parent() == Some(dir.as_path()))
+                            .count()
+                    })
+                });
+                let mut taken: Vec<Option<Vec<PathBuf>>> = assigned.into_iter().map(Some).collect();
+                assigned = Vec::with_capacity(n);
+                for (slot, bucket) in order.into_iter().enumerate() {
+                    let entries = taken[bucket].take().expect("each bucket is placed once");
+                    if opts.explain && slot != bucket {
+                        for entry in &entries {
+                            if let Some(reason) = reasons.get_mut(entry) {
+                                reason.push_str(&format!(
+                                    ", placed in folder {} (--prefer-origin)",
+                                    slot
+                                ));
+                            }
+                        }
+                    }
+                    assigned.push(entries);
+                }
+            }
+            let buckets: Vec<(String, Vec<PathBuf>)> = assigned
+                .into_iter()
+                .enumerate()
+                .map(|(i, bucket)| Ok((slot_name(i, bucket.len())?, bucket)))
+                .collect::<Result<_>>()?;
+
+            if let Some(threshold) = opts.move_threshold {
+                let folder_paths: Vec<PathBuf> = buckets
+                    .iter()
+                    .map(|(name, _)| canonical_base.join(name))
+                    .collect();
+                let entries = buckets.iter().flat_map(|(_, bucket)| bucket);
+                if current_imbalance(entries, &folder_paths).is_some_and(|pct| pct <= threshold) {
+                    return Ok(Plan {
+                        walk_errors,
+                        matched,
+                        already_balanced: true,
+                        ..Default::default()
+                    });
+                }
+            }
+
+            buckets
+        }
+        GroupBy::Hash | GroupBy::Extension | GroupBy::Parent => {
+            if opts.entries != Entries::Files {
+                let by = match opts.by {
+                    GroupBy::Hash => "hash",
+                    GroupBy::Parent => "parent",
+                    _ => "extension",
+                };
+                return Err(anyhow!("--by {} only supports --entries files", by));
+            }
+            let groups: Vec<(String, Vec<PathBuf>)> = match opts.by {
+                GroupBy::Hash => group_by_hash(files)?.into_iter().collect(),
+                GroupBy::Parent => group_by_parent(files, &resolve_base(base, opts)?, opts)
+                    .into_iter()
+                    .collect(),
+                _ if opts.subfolders > 1 => split_groups(group_by_extension(files), &stats, opts)?,
+                _ => group_by_extension(files).into_iter().collect(),
+            };
+            if opts.explain {
+                let by = match opts.by {
+                    GroupBy::Hash => "hash",
+                    GroupBy::Parent => "parent",
+                    _ => "extension",
+                };
+                for (key, entries) in &groups {
+                    for src in entries {
+                        reasons.insert(src.clone(), format!("{}={}", by, key));
+                    }
+                }
+            }
+            name_keyed_groups(groups, opts)?
+        }
+    };
+
+    let buckets: Vec<(String, Vec<PathBuf>)> = buckets.into_iter().chain(pinned).collect();
+
+    // Derived counts (--one-per-folder, --max-per-folder, keyed groupings) can explode on
+    // large inputs
+    if let Some(max) = opts.max_folders
+        && buckets.len() > max
+    {
+        return Err(anyhow!(
+            "The plan needs {} folders, more than --max-folders {}",
+            buckets.len(),
+            max
+        ));
+    }
+
+    // 3) For each bucket, work out the folder path and every entry's destination.
+    let mut folders: Vec<PlannedFolder> = Vec::with_capacity(buckets.len());
+    let mut claimed: HashSet<PathBuf> = HashSet::new();
+    let mut claimed_names: HashSet<std::ffi::OsString> = HashSet::new();
+    let mut skipped: Vec<PathBuf> = unmapped;
+    for (folder_name, bucket) in buckets {
+        let folder_path = canonical_base.join(&folder_name);
+        let resident = if opts.skip_identical_content {
+            resident_files(&folder_path, &sources)
+        } else {
+            Vec::new()
+        };
+
+        let mut moves: Vec<PlannedMove> = Vec::with_capacity(bucket.len());
+        for src in bucket {
+            if !resident.is_empty()
+                && let Some(f) = stats.get(&src)
+                && f.is_file
+                && has_identical_copy(&src, f.size, &resident)?
+            {
+                skipped.push(src);
+                continue;
+            }
+            let file_name = src
+                .file_name()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| anyhow!("Invalid filename for {}", src.display()))?;
+            // Normalize first so NFC and NFD spellings of one name land on (and collide at)
+            // the same destination
+            let file_name = match opts.normalize_unicode {
+                Some(form) => form.apply(file_name),
+                None => file_name.to_string(),
+            };
+            let file_name = match &opts.rename_template {
+                Some(template) => {
+                    let folder = folder_path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy();
+                    let name = render_file_template(template, &file_name, moves.len() + 1, &folder);
+                    if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".."
+                    {
+                        return Err(anyhow!(
+                            "--rename-template turns {} into '{}', which is not a file name",
+                            src.display(),
+                            name
+                        ));
+                    }
+                    name
+                }
+                None => file_name,
+            };
+            let mut dest = folder_path.join(opts.rename_case.apply(&file_name));
+
+            // A destination is occupied if another entry already claimed it, or if something
+            // that is not itself being moved already sits there.
+            let occupied =
+                |d: &Path| claimed.contains(d) || (d.exists() && !sources.contains(d) && d != src);
+            if occupied(&dest) {
+                match opts.on_collision {
+                    OnCollision::Error if claimed.contains(&dest) => {
+                        return Err(anyhow!(
+                            "Multiple entries would move to {} (use --on-collision rename|skip)",
+                            dest.display()
+                        ));
+                    }
+                    // Existing files are left to `--force` at move time
+                    OnCollision::Error => {}
+                    OnCollision::Rename => dest = naming::unique_dest(&dest, occupied),
+                    OnCollision::Skip => {
+                        skipped.push(src);
+                        continue;
+                    }
+                }
+            }
+            // Same name in another folder: counter it too, so the folders can be merged later
+            let name_taken = |d: &Path| d.file_name().is_some_and(|n| claimed_names.contains(n));
+            if opts.unique_names && name_taken(&dest) {
+                dest = naming::unique_dest(&dest, |d| occupied(d) || name_taken(d));
+            }
+
+            if let Some(name) = dest.file_name() {
+                claimed_names.insert(name.to_os_string());
+            }
+            claimed.insert(dest.clone());
+            let entry = stats.get(&src);
+            moves.push(collected_move(src, dest, entry));
+        }
+
+        folders.push(PlannedFolder {
+            path: folder_path,
+            moves,
+        });
+    }
+
+    if let Some(order) = opts.sort_within_folder {
+        for folder in &mut folders {
+            sort_moves(&mut folder.moves, order);
+        }
+    }
+
+    check_no_self_nesting(&folders)?;
+    if opts.strict_paths {
+        check_contained(&folders, &canonical_base)?;
+    }
+
+    Ok(Plan {
+        folders,
+        walk_errors,
+        skipped,
+        matched,
+        already_balanced: false,
+        reasons,
+    })
+}
+
+/// Put a folder's `moves` in `order`, by the size and modification time recorded when the
+/// entries were collected. Ties fall back to the destination name.
+fn sort_moves(moves: &mut [PlannedMove], order: EntryOrder) {
+    let name = |m: &PlannedMove| m.dest.file_name().map(std::ffi::OsStr::to_os_string);
+    moves.sort_by(|a, b| {
+        let by_order = match order {
+            EntryOrder::Name => std::cmp::Ordering::Equal,
+            EntryOrder::Size => b.size.cmp(&a.size),
+            EntryOrder::Mtime => a.modified.cmp(&b.modified),
+        };
+        by_order.then_with(|| name(a).cmp(&name(b)))
+    });
+}
+
+/// Longest common prefix of the entries' file names, with trailing digits and separators
+/// trimmed (`IMG_0001.jpg`, `IMG_0100.jpg` -> `IMG`). `None` if nothing is left.
+fn common_name_prefix<T: AsRef<Path>>(files: &[T]) -> Option<String> {
+    let mut names = files.iter().filter_map(|f| {
+        f.as_ref()
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+    });
+    let mut prefix = names.next()?;
+    for name in names {
+        let common = prefix
+            .char_indices()
+            .zip(name.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(prefix.len().min(name.len()), |((i, _), _)| i);
+        prefix.truncate(common);
+    }
+    let trimmed = prefix.trim_end_matches(|c: char| c.is_ascii_digit() || "-_. ".contains(c));
+    (!trimmed.is_empty()).then(|| trimmed.to_string())
+}
+
+/// How far the current layout is from an even split, when every entry already sits in one
+/// of `folders`: the largest deviation of a folder's count from the even share, as a
+/// percentage of the average folder size. `None` if any entry is outside those folders.
+fn current_imbalance<'a>(
+    files: impl IntoIterator<Item = &'a PathBuf>,
+    folders: &[PathBuf],
+) -> Option<f64> {
+    let mut counts: BTreeMap<&Path, usize> = folders.iter().map(|f| (f.as_path(), 0)).collect();
+    let mut total = 0;
+    for file in files {
+        *counts.get_mut(file.parent()?)? += 1;
+        total += 1;
+    }
+
+    let n = folders.len();
+    let (floor, ceil) = (total / n, total.div_ceil(n));
+    let worst = counts
+        .values()
+        .map(|&c| c.saturating_sub(ceil).max(floor.saturating_sub(c)))
+        .max()
+        .unwrap_or(0);
+    Some(worst as f64 / (total as f64 / n as f64) * 100.0)
+}
+
+/// Refuse plans with a destination outside `root` (the base or output directory), e.g. from
+/// `..` in a prefix or template. Every offender is listed.
+fn check_contained(folders: &[PlannedFolder], root: &Path) -> Result<()> {
+    let offenders: Vec<String> = folders
+        .iter()
+        .flat_map(|f| f.moves.iter())
+        .filter(|m| !lexical_clean(&m.dest).starts_with(root))
+        .map(|m| format!("  {} -> {}", m.src.display(), m.dest.display()))
+        .collect();
+    if offenders.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "{} destinations escape {}:\n{}",
+        offenders.len(),
+        root.display(),
+        offenders.join("\n")
+    ))
+}
+
+/// Refuse plans that would move a directory into its own subtree, e.g. `--entries dirs` with
+/// `--output-dir` inside one of the collected directories. Moving the directory would carry
+/// its target folder along with it.
+fn check_no_self_nesting(folders: &[PlannedFolder]) -> Result<()> {
+    let dir_sources: HashSet<&Path> = folders
+        .iter()
+        .flat_map(|f| f.moves.iter())
+        .filter(|m| !m.is_noop() && m.src.is_dir())
+        .map(|m| m.src.as_path())
+        .collect();
+    if dir_sources.is_empty() {
+        return Ok(());
+    }
+
+    for folder in folders {
+        if let Some(src) = folder.path.ancestors().find(|a| dir_sources.contains(a)) {
+            return Err(anyhow!(
+                "Cannot move {} while creating {} inside it; choose an output directory outside the entries being moved",
+                src.display(),
+                folder.path.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Result of `verify`: every way the tree differs from the expected distribution.
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    pub discrepancies: Vec<String>,
+}
+
+impl VerifyReport {
+    /// The tree matches the expected distribution.
+    pub fn is_ok(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+/// Gather every entry out of the target folders (`<prefix>*`) back into `base_path`: the
+/// inverse of a distribution, without needing a journal. Collisions with files already in the
+/// base follow `opts.on_collision`; with `remove_empty`, target folders left empty afterwards
+/// are removed. Options that shape the folders (count, labels, mapping, ...) are ignored.
+pub fn flatten(base_path: &str, opts: &RunOptions, remove_empty: bool) -> Result<RunReport> {
+    // A single collapsed bucket is the base itself, and the redo collection brings in the
+    // contents of every target folder
+    let gather = RunOptions {
+        subfolders: 1,
+        collapse_single: true,
+        by: GroupBy::Count,
+        one_per_folder: false,
+        max_per_folder: None,
+        folder_share: None,
+        max_bytes: None,
+        into: Vec::new(),
+        output_dir: None,
+        timestamp_folder: None,
+        mapping: None,
+        group_map: None,
+        group_map_default: None,
+        group_unmatched: None,
+        min_bucket_size: None,
+        labels: Vec::new(),
+        template: None,
+        continue_numbering: false,
+        replace_existing_folders: false,
+        prefer_origin: false,
+        move_threshold: None,
+        stage: false,
+        // Entries come back under the names they had
+        auto_prefix: false,
+        rename_case: RenameCase::None,
+        rename_template: None,
+        normalize_unicode: None,
+        unique_names: false,
+        sort_within_folder: None,
+        ..opts.clone()
+    };
+    // Only folders this flatten moved something out of are candidates for removal
+    let mut emptied: BTreeSet<PathBuf> = BTreeSet::new();
+    let report = run_with_progress(base_path, &gather, |m| {
+        if let Some(parent) = m.src.parent() {
+            emptied.insert(parent.to_path_buf());
+        }
+    })?;
+    if !remove_empty || opts.dry_run || report.cancelled {
+        return Ok(report);
+    }
+
+    for dir in emptied {
+        if !is_target_dir(&dir, &opts.prefix, opts.ignore_case) || is_protected_dir(&dir, opts) {
+            continue;
+        }
+        let mut entries = fs::read_dir(&dir)
+            .with_context(|| format!("Failed reading directory {}", dir.display()))?;
+        if entries.next().is_none() {
+            fs::remove_dir(&dir)
+                .with_context(|| format!("Failed removing empty folder {}", dir.display()))?;
+            info!("Removed empty folder {}", dir.display());
+        }
+    }
+    Ok(report)
+}
+
+/// Check that a previous distribution of `base_path` is intact.
+///
+/// The plan is recomputed with the same options; any entry that would still have to move is
+/// out of place (a loose file, or one in the wrong folder). If `opts.manifest` names the
+/// manifest written by the original run, every destination recorded there must also exist.
+pub fn verify(base_path: &str, opts: &RunOptions) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+
+    if let Some(manifest_path) = &opts.manifest {
+        // Relative destinations come from a `relative_output` run
+        let base = resolve_base(Path::new(base_path), opts)?;
+        for m in manifest::read(manifest_path)? {
+            if !base.join(&m.dest).exists() {
+                report
+                    .discrepancies
+                    .push(format!("missing: {}", m.dest.display()));
+            }
+        }
+    }
+
+    let plan = plan(base_path, opts)?;
+    for m in plan.moves().filter(|m| !m.is_noop()) {
+        report.discrepancies.push(format!(
+            "out of place: {} (expected {})",
+            m.src.display(),
+            m.dest.display()
+        ));
+    }
+
+    Ok(report)
+}
+
+/// Moves a run with `opts` would still make on `base_path` as it is now. An empty result
+/// means the current layout is a fixed point: running again would not move anything.
+pub fn check_stable(base_path: &str, opts: &RunOptions) -> Result<Vec<PlannedMove>> {
+    Ok(plan(base_path, opts)?
+        .moves()
+        .filter(|m| !m.is_noop())
+        .cloned()
+        .collect())
+}
+
+/// Number of entries a run with `opts` would pick up under `base_path`, after every filter.
+/// Only the walk runs: nothing is planned or moved.
+pub fn count_matches(base_path: &str, opts: &RunOptions) -> Result<usize> {
+    let base = Path::new(base_path);
+    if !base.is_dir() {
+        return Err(anyhow!("Path '{}' is not a directory", base.display()));
+    }
+    Ok(collect_files(base, opts)?.files.len())
+}
+
+/// Check that `folder` can be filled and return the staging directory it is filled under, if
+/// any. Nothing is created here: the first entry actually moved in creates the directory, so
+/// a folder whose entries are all left behind never appears.
+fn prepare_folder(folder: &PlannedFolder, opts: &RunOptions) -> Result<Option<PathBuf>> {
+    // A new folder is filled under a hidden name and appears only once complete
+    let staging = (opts.stage && !folder.path.exists()).then(|| staging_dir(&folder.path));
+    if let Some(staging) = &staging {
+        if staging.exists() {
+            return Err(anyhow!(
+                "Staging directory {} is left over from an earlier run; remove it first",
+                staging.display()
+            ));
+        }
+    } else if folder.path.exists() && !folder.path.is_dir() {
+        return Err(anyhow!(
+            "Destination path {} exists and is not a directory",
+            folder.path.display()
+        ));
+    }
+    Ok(staging)
+}
+
+/// Print a rendered dry-run preview, or keep it in `report` under `opts.capture_preview`.
+fn show_preview(preview: String, opts: &RunOptions, report: &mut RunReport) {
+    if opts.capture_preview {
+        report.preview.push_str(&preview);
+    } else {
+        print!("{}", preview);
+    }
+}
+
+fn is_cancelled(opts: &RunOptions) -> bool {
+    opts.cancel
+        .as_ref()
+        .is_some_and(|c| c.load(std::sync::atomic::Ordering::Relaxed))
+}
+
+/// Carry out one planned move, to `dest` (the staged path under `opts.stage`). Returns the
+/// bytes moved, or `None` when the entry is left where it is: gone since the walk, not newer
+/// under `opts.update`, or locked under `opts.skip_locked`.
+fn move_one(m: &PlannedMove, dest: &Path, opts: &RunOptions) -> Result<Option<u64>> {
+    // On a live directory the entry may have gone since the walk
+    if fs::symlink_metadata(&m.src).is_err_and(|err| err.kind() == std::io::ErrorKind::NotFound) {
+        warn!("skipping {}: it no longer exists", m.src.display());
+        return Ok(None);
+    }
+    let force = if opts.update && m.dest.exists() {
+        if !is_newer(m.modified, &m.dest) {
+            return Ok(None);
+        }
+        true
+    } else {
+        opts.force
+    };
+    if let Some(parent) = dest.parent()
+        && !parent.exists()
+    {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    match move_entry(&m.src, dest, m.size, force) {
+        Ok(bytes) => {
+            debug!("moved {} -> {}", m.src.display(), m.dest.display());
+            Ok(Some(bytes))
+        }
+        Err(err) if opts.skip_locked && is_locked_error(&err) => {
+            warn!("skipping locked entry {}: {:#}", m.src.display(), err);
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Carry out every move of `plan` on a pool of worker threads (`opts.threads`, or one per
+/// core) fed through a channel: each folder is checked and its moves queued while the
+/// workers are already moving the entries of earlier folders. Results are reported in plan
+/// order, so `on_move`, the journal and `--format jsonl` lines read as in a sequential run.
+/// Returns, per folder, how many entries were left where they were.
+fn move_parallel(
+    plan: &Plan,
+    shown: &Plan,
+    opts: &RunOptions,
+    report: &mut RunReport,
+    journal: &mut Option<journal::Writer>,
+    moved: &mut HashSet<(usize, usize)>,
+    on_move: &mut impl FnMut(&PlannedMove),
+) -> Result<Vec<usize>> {
+    if opts.stage {
+        return Err(anyhow!(
+            "--stage cannot be combined with parallel moves: a folder is only renamed into place once all of its entries have moved"
+        ));
+    }
+    let workers = opts
+        .threads
+        .or_else(|| std::thread::available_parallelism().ok().map(usize::from))
+        .unwrap_or(1)
+        .max(1);
+    let mut left = vec![0; plan.folders.len()];
+
+    let (jobs, queue) = std::sync::mpsc::sync_channel::<(usize, usize)>(workers * 4);
+    let queue = std::sync::Mutex::new(queue);
+    let (done, results) = std::sync::mpsc::channel();
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            let (queue, done) = (&queue, done.clone());
+            scope.spawn(move || {
+                loop {
+                    // The lock is only held while waiting for the next job
+                    let job = queue.lock().expect("job queue poisoned").recv();
+                    let Ok((f, i)) = job else {
+                        break;
+                    };
+                    let m = &plan.folders[f].moves[i];
+                    if done.send((f, i, move_one(m, &m.dest, opts))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(done);
+
+        // Queue everything, then drain the results. Results that arrive while queueing are
+        // kept aside; a failure among them stops further queueing, as in a sequential run.
+        let mut failed = None;
+        let mut queued: Vec<(usize, usize)> = Vec::new();
+        let mut arrived: HashMap<(usize, usize), Result<Option<u64>>> = HashMap::new();
+        let mut errored = false;
+        'folders: for (f, folder) in plan.folders.iter().enumerate() {
+            if let Err(err) = prepare_folder(folder, opts) {
+                failed = Some(err);
+                break;
+            }
+            for (i, m) in folder.moves.iter().enumerate() {
+                if m.is_noop() {
+                    continue;
+                }
+                if is_cancelled(opts) {
+                    report.cancelled = true;
+                    left[f] += 1;
+                    continue;
+                }
+                for (f, i, result) in results.try_iter() {
+                    errored |= result.is_err();
+                    arrived.insert((f, i), result);
+                }
+                if errored || jobs.send((f, i)).is_err() {
+                    break 'folders;
+                }
+                queued.push((f, i));
+            }
+        }
+        drop(jobs);
+
+        // A result is held back until every move queued before it has come back
+        let mut incoming = results.into_iter();
+        let mut next = 0;
+        loop {
+            let Some(result) = queued.get(next).and_then(|key| arrived.remove(key)) else {
+                match incoming.next() {
+                    Some((f, i, result)) => {
+                        arrived.insert((f, i), result);
+                        continue;
+                    }
+                    None => break,
+                }
+            };
+            let (f, i) = queued[next];
+            next += 1;
+            match result {
+                Ok(Some(bytes)) => {
+                    let shown_move = &shown.folders[f].moves[i];
+                    if let Some(journal) = journal
+                        && let Err(err) = journal.record(shown_move)
+                    {
+                        failed.get_or_insert(err);
+                    }
+                    if opts.format == OutputFormat::Jsonl {
+                        println!("{}", json::move_object(shown_move));
+                    }
+                    report.bytes_moved += bytes;
+                    moved.insert((f, i));
+                    on_move(&plan.folders[f].moves[i]);
+                }
+                Ok(None) => left[f] += 1,
+                Err(err) => {
+                    failed.get_or_insert(err);
+                }
+            }
+        }
+        match failed {
+            Some(err) => Err(err),
+            None => Ok(left),
+        }
+    })
+}
+
+/// Move a single file or directory of `size` bytes from `src` to `dest`, replacing an
+/// existing destination only when `force` is set. Returns the number of bytes moved.
+fn move_entry(src: &Path, dest: &Path, size: u64, force: bool) -> Result<u64> {
+    if dest.exists() {
+        if !force {
+            return Err(anyhow!(
+                "Destination file {} already exists (use --force to overwrite)",
+                dest.display()
+            ));
+        } else if dest.is_dir() {
+            fs::remove_dir_all(dest).with_context(|| {
+                format!(
+                    "Failed removing existing destination directory {}",
+                    dest.display()
+                )
+            })?;
+        } else {
+            fs::remove_file(dest).with_context(|| {
+                format!(
+                    "Failed removing existing destination file {}",
+                    dest.display()
+                )
+            })?;
+        }
+    }
+
+    match fs::rename(src, dest) {
+        Ok(_) => Ok(size),
+        // Directories have no copy fallback; a failed rename is final.
+        Err(rename_err) if src.is_dir() => Err(anyhow::Error::new(rename_err).context(format!(
+            "Failed moving directory {} to {}",
+            src.display(),
+            dest.display()
+        ))),
+        Err(rename_err) => {
+            let copied = fs::copy(src, dest).with_context(|| {
+                format!(
+                    "Failed copying {} to {}: {}",
+                    src.display(),
+                    dest.display(),
+                    rename_err
+                )
+            })?;
+            fs::remove_file(src)
+                .with_context(|| format!("Failed removing original file {}", src.display()))?;
+            Ok(copied)
+        }
+    }
+}
+
+/// Whether a source last modified at `src` is newer than `dest`. Unknown times count as not
+/// newer.
+fn is_newer(src: Option<std::time::SystemTime>, dest: &Path) -> bool {
+    match (src, fs::metadata(dest).and_then(|m| m.modified()).ok()) {
+        (Some(src), Some(dest)) => src > dest,
+        _ => false,
+    }
+}
+
+/// Whether a failed move was caused by the entry being open or locked elsewhere (a Windows
+/// sharing or lock violation, a busy resource, or a permission error), as opposed to a
+/// problem that should stop the run.
+fn is_locked_error(err: &anyhow::Error) -> bool {
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+    const WINDOWS_LOCK_CODES: [i32; 2] = [32, 33];
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .any(|io| {
+            matches!(
+                io.kind(),
+                std::io::ErrorKind::PermissionDenied
+                    | std::io::ErrorKind::ResourceBusy
+                    | std::io::ErrorKind::ExecutableFileBusy
+            ) || (cfg!(windows)
+                && io
+                    .raw_os_error()
+                    .is_some_and(|code| WINDOWS_LOCK_CODES.contains(&code)))
+        })
+}
+
+/// The planned move of `src` to `dest`, with the size and modification time `entry` was
+/// collected with. A directory's size is the total under it, so only directories are walked.
+fn collected_move(src: PathBuf, dest: PathBuf, entry: Option<&CollectedFile>) -> PlannedMove {
+    match entry {
+        Some(entry) => PlannedMove {
+            size: match entry.is_dir {
+                true => entry_size(&src),
+                false => entry.size,
+            },
+            modified: entry.modified,
+            src,
+            dest,
+        },
+        None => PlannedMove::new(src, dest),
+    }
+}
+
+/// Size of a file, or the total size of the files under a directory. Unreadable entries
+/// count as empty.
+fn entry_size(path: &Path) -> u64 {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    fs::read_dir(path)
+        .map(|rd| {
+            rd.filter_map(Result::ok)
+                .map(|e| entry_size(&e.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// Format a byte count with binary units, e.g. `1.5 KiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Entries gathered by `collect_files`, plus the walk errors for anything it had to skip.
+struct Collected {
+    files: Vec<CollectedFile>,
+    walk_errors: Vec<String>,
+}
+
+/// A collected entry with the metadata the pipeline needs, so filtering, sorting and
+/// partitioning never stat it again.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct CollectedFile {
+    path: PathBuf,
+    /// Length in bytes; for a directory, the directory entry itself, not its contents.
+    size: u64,
+    modified: Option<std::time::SystemTime>,
+    is_file: bool,
+    is_dir: bool,
+}
+
+impl CollectedFile {
+    /// Stat `path` once. `None` if it cannot be read, e.g. it was removed during the walk.
+    fn stat(path: PathBuf) -> Option<CollectedFile> {
+        let meta = fs::metadata(&path).ok()?;
+        Some(CollectedFile {
+            size: meta.len(),
+            modified: meta.modified().ok(),
+            is_file: meta.is_file(),
+            is_dir: meta.is_dir(),
+            path,
+        })
+    }
+
+    /// Whether this is a directory that looks like one of our target folders.
+    fn is_target_dir(&self, prefix: &str, ignore_case: bool) -> bool {
+        self.is_dir && is_target_name(&self.path, prefix, ignore_case)
+    }
+}
+
+impl AsRef<Path> for CollectedFile {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Collect files matching `opts.matching` under `base`. If an existing folder with the prefix
+/// exists under `base` we also collect matching files inside it (one-level) so we can `redo`
+/// distributions.
+///
+/// `opts.entries` selects whether files, directories or both are returned. Collected directories
+/// are moved whole, so anything nested inside one of them is dropped from the result.
+///
+/// Entries the walker cannot read are skipped with a warning (silently with
+/// `opts.quiet_errors`) and recorded in `walk_errors`, unless `opts.strict` is set, in which case the first such error is returned.
+fn collect_files(base: &Path, opts: &RunOptions) -> Result<Collected> {
+    collect_files_with(base, opts, &CollectedFile::stat)
+}
+
+/// `collect_files` with `stat` reading each walked entry's metadata.
+fn collect_files_with(
+    base: &Path,
+    opts: &RunOptions,
+    stat: &dyn Fn(PathBuf) -> Option<CollectedFile>,
+) -> Result<Collected> {
+    let pattern = opts.matching.as_str();
+    let prefix = opts.prefix.as_str();
+    let entries = opts.entries;
+    let mut exclude = opts.exclude.clone();
+    exclude.extend(read_ignore_file(base)?);
+    let excludes = build_excludes(&exclude, opts.ignore_case)?;
+    let since = if opts.since_run {
+        let journal_path = opts
+            .journal
+            .as_ref()
+            .ok_or_else(|| anyhow!("--since-run needs --journal"))?;
+        journal::last_run(journal_path)?
+    } else {
+        None
+    };
+    let mirrored = match &opts.mirror {
+        Some(reference) => Some(names_under(reference)?),
+        None => None,
+    };
+    // Only globs go to the walker; regex and literal patterns filter the names it finds
+    let name_regex = match opts.match_type {
+        MatchType::Regex => Some(
+            regex::RegexBuilder::new(pattern)
+                .case_insensitive(opts.ignore_case)
+                .build()
+                .with_context(|| format!("Invalid --matching regex '{}'", pattern))?,
+        ),
+        _ => None,
+    };
+    let name_matches = |path: &Path| {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default();
+        match opts.match_type {
+            MatchType::Glob => true,
+            MatchType::Regex => name_regex.as_ref().is_some_and(|re| re.is_match(&name)),
+            MatchType::Literal if opts.ignore_case => name.to_lowercase() == pattern.to_lowercase(),
+            MatchType::Literal => name == pattern,
+        }
+    };
+    // Shared by the primary walk and the redo collection, so a redo never brings back
+    // entries the user filtered out.
+    let wanted = |f: &CollectedFile| {
+        entries.accepts(f)
+            && name_matches(&f.path)
+            && matches_extension(&f.path, &opts.ext)
+            && !is_excluded(&f.path, &excludes, opts.skip_hidden)
+            // Unknown times count as modified
+            && since.is_none_or(|since| f.modified.is_none_or(|m| m >= since))
+            && mirrored
+                .as_ref()
+                .is_none_or(|names| f.path.file_name().is_none_or(|n| !names.contains(n)))
+    };
+
+    // Always resolve base to an absolute path first
+    let canonical_base = resolve_base(base, opts)?;
+
+    // Use string form — avoids internal strip_prefix panics in globwalk
+    let base_str = canonical_base
+        .to_str()
+        .ok_or_else(|| anyhow!("Base path is not valid UTF-8"))?
+        .to_string();
+
+    let max_depth = if opts.recursive { usize::MAX } else { 1 };
+    let walk_pattern = match opts.match_type {
+        MatchType::Glob => pattern,
+        MatchType::Regex | MatchType::Literal if opts.extra_patterns.is_empty() => "*",
+        _ => {
+            return Err(anyhow!(
+                "Several --matching patterns need --match-type glob"
+            ));
+        }
+    };
+    let prune: Vec<String> = opts
+        .prune_dir
+        .iter()
+        .cloned()
+        .chain(
+            opts.exclude_dir_name
+                .iter()
+                .map(|name| globset::escape(name)),
+        )
+        .collect();
+    let patterns = walk::patterns(walk_pattern, &opts.extra_patterns, &prune);
+    let mut walk_errors: Vec<String> = Vec::new();
+
+    let walked: Vec<PathBuf> = match opts.threads {
+        Some(threads) => {
+            let (found, errors) = walk::parallel_walk(
+                &canonical_base,
+                &patterns,
+                opts.ignore_case,
+                max_depth,
+                threads,
+            )?;
+            for err in errors {
+                record_walk_error(err, opts, &mut walk_errors)?;
+            }
+            found
+        }
+        None => {
+            // Build walker using the canonical absolute path string
+            let walker = GlobWalkerBuilder::from_patterns(&base_str, &patterns)
+                .case_insensitive(opts.ignore_case)
+                .max_depth(max_depth)
+                .build()
+                .with_context(|| format!("Failed building glob walker for {}", base_str))?;
+
+            let mut found = Vec::new();
+            for entry in walker {
+                if let Some(p) = walk_entry(entry, opts, &mut walk_errors)? {
+                    found.push(p);
+                }
+            }
+            found
+        }
+    };
+
+    let mut files: Vec<CollectedFile> = Vec::with_capacity(walked.len());
+    for p in walked {
+        let Some(f) = stat(p) else {
+            continue;
+        };
+        // Existing target folders are redo sources, never entries to distribute themselves
+        if wanted(&f)
+            && !(f.path.parent() == Some(canonical_base.as_path())
+                && f.is_target_dir(prefix, opts.ignore_case))
+        {
+            files.push(f);
+        }
+    }
+
+    // Handle redo-existing prefix-* directories. With --recursive the main walk has already
+    // seen their contents, so dedup through a set rather than scanning `files` per entry.
+    // --continue-numbering leaves them as they are.
+    let mut seen: HashSet<PathBuf> = files.iter().map(|f| f.path.clone()).collect();
+    let redo = if opts.continue_numbering {
+        None
+    } else {
+        fs::read_dir(&canonical_base).ok()
+    };
+    if let Some(readdir) = redo {
+        for entry in readdir.filter_map(Result::ok) {
+            if is_target_dir(&entry.path(), prefix, opts.ignore_case)
+                && !is_protected_dir(&entry.path(), opts)
+            {
+                let inner_base = resolve_base(&entry.path(), opts)?;
+                let inner_str = inner_base
+                    .to_str()
+                    .ok_or_else(|| anyhow!("Invalid UTF-8 path"))?;
+                let inner_walker = GlobWalkerBuilder::from_patterns(inner_str, &patterns)
+                    .case_insensitive(opts.ignore_case)
+                    .max_depth(1)
+                    .build()
+                    .with_context(|| format!("Failed to build walker for {}", inner_str))?;
+
+                for e in inner_walker {
+                    let Some(p) = walk_entry(e, opts, &mut walk_errors)? else {
+                        continue;
+                    };
+                    if seen.contains(&p) {
+                        continue;
+                    }
+                    let Some(f) = stat(p) else {
+                        continue;
+                    };
+                    // A target-like folder nested in a target folder is not an entry either
+                    if wanted(&f) && !f.is_target_dir(prefix, opts.ignore_case) {
+                        seen.insert(f.path.clone());
+                        files.push(f);
+                    }
+                }
+            }
+        }
+    }
+
+    let key = opts
+        .sort_key
+        .as_deref()
+        .map(|key| {
+            regex::Regex::new(key).with_context(|| format!("Invalid --sort-key pattern '{}'", key))
+        })
+        .transpose()?;
+    sort::order(
+        &mut files,
+        opts.collate,
+        key.as_ref(),
+        opts.strip_prefix.as_deref(),
+    );
+
+    if entries != Entries::Files {
+        // Drop anything that lives inside a collected directory: it moves with its parent.
+        let dirs: HashSet<PathBuf> = files
+            .iter()
+            .filter(|f| f.is_dir)
+            .map(|f| f.path.clone())
+            .collect();
+        files.retain(|f| !f.path.ancestors().skip(1).any(|a| dirs.contains(a)));
+    }
+
+    Ok(Collected { files, walk_errors })
+}
+
+/// Absolute form of `path` used for every source and destination. Normally this is the
+/// canonical path; with `opts.no_canonicalize` it is the logical absolute path instead, which
+/// keeps symlinked prefixes intact.
+fn resolve_base(path: &Path, opts: &RunOptions) -> Result<PathBuf> {
+    if !opts.no_canonicalize {
+        return fs::canonicalize(path)
+            .with_context(|| format!("Failed to canonicalize {}", path.display()));
+    }
+
+    let absolute = std::path::absolute(path)
+        .with_context(|| format!("Failed to make {} absolute", path.display()))?;
+    Ok(lexical_clean(&absolute))
+}
+
+/// Resolve `.` and `..` components lexically, without consulting the filesystem.
+fn lexical_clean(path: &Path) -> PathBuf {
+    let mut logical = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                logical.pop();
+            }
+            other => logical.push(other),
+        }
+    }
+    logical
+}
+
+/// Unwrap a single walker result. Errors are fatal under `strict`; otherwise they are
+/// recorded in `walk_errors` (and printed unless `quiet_errors`) and the entry is skipped.
+fn walk_entry(
+    entry: std::result::Result<globwalk::DirEntry, globwalk::WalkError>,
+    opts: &RunOptions,
+    walk_errors: &mut Vec<String>,
+) -> Result<Option<PathBuf>> {
+    match entry {
+        Ok(e) => Ok(Some(e.path().to_path_buf())),
+        Err(err) => {
+            record_walk_error(err.to_string(), opts, walk_errors)?;
+            Ok(None)
+        }
+    }
+}
+
+/// Handle a walk error: fatal under `strict`, otherwise record it and warn unless
+/// `quiet_errors`.
+fn record_walk_error(err: String, opts: &RunOptions, walk_errors: &mut Vec<String>) -> Result<()> {
+    if opts.strict {
+        return Err(anyhow!("Walk error (strict mode): {}", err));
+    }
+    if !opts.quiet_errors {
+        warn!("skipping entry due to error: {}", err);
+    }
+    walk_errors.push(err);
+    Ok(())
+}
+
+/// Whether `path` has one of `extensions` (case-insensitive, leading dots ignored).
+/// An empty list accepts everything.
+fn matches_extension(path: &Path, extensions: &[String]) -> bool {
+    if extensions.is_empty() {
+        return true;
+    }
+    let Some(ext) = path.extension().map(|e| e.to_string_lossy()) else {
+        return false;
+    };
+    extensions
+        .iter()
+        .any(|want| want.trim_start_matches('.').eq_ignore_ascii_case(&ext))
+}
+
+/// File and directory names anywhere under `dir`, for `--mirror`.
+fn names_under(dir: &Path) -> Result<HashSet<std::ffi::OsString>> {
+    if !dir.is_dir() {
+        return Err(anyhow!(
+            "Mirror reference '{}' is not a directory",
+            dir.display()
+        ));
+    }
+    let mut names = HashSet::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let entries = fs::read_dir(&current)
+            .with_context(|| format!("Failed to read {}", current.display()))?;
+        for entry in entries {
+            let entry = entry.with_context(|| format!("Failed to read {}", current.display()))?;
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                pending.push(entry.path());
+            }
+            names.insert(entry.file_name());
+        }
+    }
+    Ok(names)
+}
+
+/// Per-directory exclude file, read from the base path.
+pub const IGNORE_FILE: &str = ".refolderignore";
+
+/// Glob lines of the base's `.refolderignore`, one pattern per line; blank lines and lines
+/// starting with `#` are skipped. The file itself is always excluded when present.
+fn read_ignore_file(base: &Path) -> Result<Vec<String>> {
+    let path = base.join(IGNORE_FILE);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+        }
+    };
+    let mut patterns = vec![IGNORE_FILE.to_string()];
+    patterns.extend(
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string),
+    );
+    Ok(patterns)
+}
+
+/// Compile `--exclude` and `.refolderignore` patterns, matched against entry names.
+fn build_excludes(patterns: &[String], ignore_case: bool) -> Result<globset::GlobSet> {
+    let mut builder = globset::GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = globset::GlobBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .with_context(|| format!("Invalid exclude pattern '{}'", pattern))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+/// Whether the name of `path` matches an exclude pattern, or is hidden under `skip_hidden`.
+fn is_excluded(path: &Path, excludes: &globset::GlobSet, skip_hidden: bool) -> bool {
+    let Some(name) = path.file_name() else {
+        return false;
+    };
+    (skip_hidden && name.to_string_lossy().starts_with('.')) || excludes.is_match(name)
+}
+
+/// Files already in `folder` that are not being moved, with their sizes.
+fn resident_files(folder: &Path, sources: &HashSet<PathBuf>) -> Vec<(PathBuf, u64)> {
+    let Ok(readdir) = fs::read_dir(folder) else {
+        return Vec::new();
+    };
+    readdir
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| !sources.contains(p))
+        .filter_map(|p| {
+            let meta = fs::metadata(&p).ok()?;
+            meta.is_file().then_some((p, meta.len()))
+        })
+        .collect()
+}
+
+/// Whether one of `resident` has the same content as `src`, which is `size` bytes long. Only
+/// files of the same size are hashed.
+fn has_identical_copy(src: &Path, size: u64, resident: &[(PathBuf, u64)]) -> Result<bool> {
+    let mut src_hash = None;
+    for (path, _) in resident.iter().filter(|(_, len)| *len == size) {
+        let src_hash = match &src_hash {
+            Some(hash) => hash,
+            None => src_hash.insert(hash::sha256_hex(src)?),
+        };
+        if hash::sha256_hex(path)? == *src_hash {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Hidden sibling a new target folder is filled in under `--stage`: `.pack-1.tmp` for
+/// `pack-1`.
+fn staging_dir(folder: &Path) -> PathBuf {
+    let name = folder.file_name().unwrap_or_default().to_string_lossy();
+    folder.with_file_name(format!(".{}.tmp", name))
+}
+
+/// Whether `path` is a directory that looks like one of our target folders.
+fn is_target_dir(path: &Path, prefix: &str, ignore_case: bool) -> bool {
+    path.is_dir() && is_target_name(path, prefix, ignore_case)
+}
+
+/// Whether `path` is named in `opts.exclude_dir_name`, so it is never a source or target.
+fn is_protected_dir(path: &Path, opts: &RunOptions) -> bool {
+    path.file_name()
+        .is_some_and(|n| opts.exclude_dir_name.iter().any(|name| n == name.as_str()))
+}
+
+/// Whether the name of `path` starts with the target folder prefix, compared with the same
+/// case sensitivity as the glob.
+fn is_target_name(path: &Path, prefix: &str, ignore_case: bool) -> bool {
+    path.file_name().is_some_and(|n| {
+        let name = n.to_string_lossy();
+        if ignore_case {
+            name.to_lowercase().starts_with(&prefix.to_lowercase())
+        } else {
+            name.starts_with(prefix)
+        }
+    })
+}
+
+/// Highest numeric suffix among the `<prefix><separator><number>` folders directly in `dir`,
+/// or `None` if there are none.
+fn highest_suffix(dir: &Path, opts: &RunOptions) -> Result<Option<usize>> {
+    Ok(numbered_folders(dir, opts)?
+        .into_iter()
+        .map(|(number, _)| number)
+        .max())
+}
+
+/// The `<prefix><separator><number>` folders directly in `dir` with their numbers.
+fn numbered_folders(dir: &Path, opts: &RunOptions) -> Result<Vec<(usize, PathBuf)>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let readdir =
+        fs::read_dir(dir).with_context(|| format!("Failed reading directory {}", dir.display()))?;
+    let mut numbered = Vec::new();
+    for entry in readdir.filter_map(Result::ok) {
+        let path = entry.path();
+        if !is_target_dir(&path, &opts.prefix, opts.ignore_case) || is_protected_dir(&path, opts) {
+            continue;
+        }
+        if let Some(number) = folder_number(&entry.file_name().to_string_lossy(), opts) {
+            numbered.push((number, path));
+        }
+    }
+    Ok(numbered)
+}
+
+/// The number in a `<prefix><separator><number>` folder name.
+fn folder_number(name: &str, opts: &RunOptions) -> Option<usize> {
+    name.get(opts.prefix.len()..)
+        .and_then(|rest| rest.strip_prefix(opts.separator.as_str()))
+        .filter(|digits| digits.chars().all(|c| c.is_ascii_digit()))
+        .and_then(|digits| digits.parse::<usize>().ok())
+}
+
+/// Remove the empty numbered target folders above the count `plan` uses, e.g. `pack-4` and
+/// `pack-5` after a redo from five folders down to three. Folders that still hold anything
+/// are left alone.
+fn remove_stale_folders(base: &Path, opts: &RunOptions, plan: &Plan) -> Result<()> {
+    let target_base = opts.output_dir.as_deref().unwrap_or(base);
+    // By number rather than by path, so it holds whether or not the plan's paths are canonical
+    let count = plan
+        .folders
+        .iter()
+        .filter_map(|f| f.path.file_name())
+        .filter_map(|name| folder_number(&name.to_string_lossy(), opts))
+        .max()
+        .unwrap_or(plan.folders.len());
+    for (number, dir) in numbered_folders(target_base, opts)? {
+        if number <= count {
+            continue;
+        }
+        let mut entries = fs::read_dir(&dir)
+            .with_context(|| format!("Failed reading directory {}", dir.display()))?;
+        if entries.next().is_some() {
+            continue;
+        }
+        fs::remove_dir(&dir)
+            .with_context(|| format!("Failed removing empty folder {}", dir.display()))?;
+        info!("Removed empty folder {}", dir.display());
+    }
+    Ok(())
+}
+
+/// Group files by the leading hex characters of their SHA-256.
+fn group_by_hash(files: Vec<PathBuf>) -> Result<BTreeMap<String, Vec<PathBuf>>> {
+    let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for file in files {
+        let digest = hash::sha256_hex(&file)?;
+        groups
+            .entry(digest[..HASH_PREFIX_LEN].to_string())
+            .or_default()
+            .push(file);
+    }
+    Ok(groups)
+}
+
+/// Group files by lowercased extension.
+fn group_by_extension(files: Vec<PathBuf>) -> BTreeMap<String, Vec<PathBuf>> {
+    let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for file in files {
+        let key = file
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_else(|| NO_EXTENSION_KEY.to_string());
+        groups.entry(key).or_default().push(file);
+    }
+    groups
+}
+
+/// Group files by the name of the directory they sit in. Files directly in `base` get
+/// `NO_PARENT_KEY`. A file already in one of our `<prefix><separator><key>` folders keeps its
+/// key, so a redo leaves it in place instead of nesting the prefix again.
+fn group_by_parent(
+    files: Vec<PathBuf>,
+    base: &Path,
+    opts: &RunOptions,
+) -> BTreeMap<String, Vec<PathBuf>> {
+    let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for file in files {
+        let key = match file.parent() {
+            Some(parent) if parent != base => {
+                let name = parent
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                let earlier_key = (parent.parent() == Some(base)
+                    && is_target_dir(parent, &opts.prefix, opts.ignore_case))
+                .then(|| name.get(opts.prefix.len()..))
+                .flatten()
+                .and_then(|rest| rest.strip_prefix(opts.separator.as_str()))
+                .filter(|key| !key.is_empty());
+                earlier_key.map_or(name.clone(), str::to_string)
+            }
+            _ => NO_PARENT_KEY.to_string(),
+        };
+        groups.entry(key).or_default().push(file);
+    }
+    groups
+}
+
+/// Folder name for the 0-based bucket `index` holding `count` entries: the matching entry of
+/// `opts.labels` if given, then `opts.template` if set, otherwise prefix, separator and suffix
+/// as in `folder_name`. Cycled labels get a round number: `Q1`, ..., `Q4`, `Q1-2`.
+fn bucket_name(opts: &RunOptions, index: usize, pad: usize, count: usize) -> Result<String> {
+    if !opts.labels.is_empty() {
+        let round = index / opts.labels.len();
+        let label = &opts.labels[index % opts.labels.len()];
+        return match round {
+            0 => Ok(label.clone()),
+            _ if opts.cycle_labels => Ok(format!("{}{}{}", label, opts.separator, round + 1)),
+            _ => Err(anyhow!(
+                "{} labels given but the plan needs more folders (use --cycle-labels to reuse them)",
+                opts.labels.len()
+            )),
+        };
+    }
+    match &opts.template {
+        Some(template) => {
+            let suffix = folder_name("", index, opts.suffix, "", opts.start_index, pad)?;
+            Ok(naming::render_template(
+                template,
+                &opts.prefix,
+                &suffix,
+                count,
+            ))
+        }
+        None => folder_name(
+            &opts.prefix,
+            index,
+            opts.suffix,
+            &opts.separator,
+            opts.start_index,
+            pad,
+        ),
+    }
+}
+
+/// Split every group into up to `opts.subfolders` buckets (fewer for small groups), keyed
+/// `<key><separator><suffix>`, e.g. `jpg-1`, `jpg-2`, `png-1`.
+fn split_groups(
+    groups: BTreeMap<String, Vec<PathBuf>>,
+    stats: &HashMap<PathBuf, CollectedFile>,
+    opts: &RunOptions,
+) -> Result<Vec<(String, Vec<PathBuf>)>> {
+    let mut split = Vec::new();
+    for (key, files) in groups {
+        let n = opts.subfolders.min(files.len());
+        // Every split of the group would share the bare key and pile into one folder
+        if opts.suffix == SuffixStyle::None && n > 1 {
+            return Err(anyhow!(
+                "--suffix none names every split of '{}' the same; it needs exactly one folder per group, got {}",
+                key,
+                n
+            ));
+        }
+        let pad = opts
+            .pad
+            .width(opts.start_index + n.saturating_sub(1), opts.suffix);
+        let sizes: Vec<u64> = files
+            .iter()
+            .map(|f| stats.get(f).map_or(0, |s| s.size))
+            .collect();
+        for (i, bucket) in
+            assign::assign_sized(files, &sizes, n, opts.strategy, opts.remainder, opts.fill)
+                .into_values()
+                .enumerate()
+        {
+            let sub_key =
+                folder_name(&key, i, opts.suffix, &opts.separator, opts.start_index, pad)?;
+            split.push((sub_key, bucket));
+        }
+    }
+    Ok(split)
+}
+
+/// Name each keyed group's folder according to `opts.folder_name_from`. Folders are returned
+/// in key order.
+fn name_keyed_groups(
+    groups: Vec<(String, Vec<PathBuf>)>,
+    opts: &RunOptions,
+) -> Result<Vec<(String, Vec<PathBuf>)>> {
+    // Numbered by index, every group would share the bare prefix
+    if opts.folder_name_from == Some(FolderNameFrom::Index)
+        && opts.suffix == SuffixStyle::None
+        && opts.template.is_none()
+        && opts.labels.is_empty()
+        && groups.len() > 1
+    {
+        return Err(anyhow!(
+            "--suffix none names every folder '{}'; it needs exactly one folder, got {}",
+            opts.prefix,
+            groups.len()
+        ));
+    }
+    let pad = opts.pad.width(
+        opts.start_index + groups.len().saturating_sub(1),
+        opts.suffix,
+    );
+    groups
+        .into_iter()
+        .enumerate()
+        .map(|(i, (key, files))| {
+            let name = match opts.folder_name_from {
+                None => format!("{}{}{}", opts.prefix, opts.separator, key),
+                Some(FolderNameFrom::Key) => key,
+                Some(FolderNameFrom::Index) => bucket_name(opts, i, pad, files.len())?,
+            };
+            Ok((name, files))
+        })
+        .collect()
+}
+
+/// Write every move `plan` makes as one JSON object per line, flushing as it goes so a
+/// reader such as `jq` sees each line straight away. Entries already in place are left out.
+pub fn write_jsonl(out: &mut impl std::io::Write, plan: &Plan) -> std::io::Result<()> {
+    for m in plan.moves().filter(|m| !m.is_noop()) {
+        writeln!(out, "{}", json::move_object(m))?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// Print the dry-run tree and summary for `file_moves` to stdout, in the default theme.
+pub fn print_dry_run_preview(file_moves: &[(String, String)]) -> std::io::Result<()> {
+    write_dry_run_preview(&mut std::io::stdout().lock(), file_moves, &Theme::default())
+}
+
+/// Write the dry-run tree and summary for `file_moves` to `out`, drawn with `theme`;
+/// `print_dry_run_preview` is this with the default theme on stdout.
+pub fn write_dry_run_preview(
+    out: &mut impl std::io::Write,
+    file_moves: &[(String, String)],
+    theme: &Theme,
+) -> std::io::Result<()> {
+    out.write_all(render_dry_run_preview(file_moves, &PreviewOptions::default(), theme).as_bytes())
+}
+
+/// One line of the `OutputFormat::Table` preview.
+struct TableRow {
+    folder: String,
+    file: String,
+    size: u64,
+}
+
+/// Render `rows` as left-aligned `FOLDER` and `FILE` columns and a right-aligned `SIZE`
+/// column, each as wide as its longest cell, under a header and a dashed rule.
+fn render_table(rows: &[TableRow]) -> String {
+    use std::fmt::Write;
+
+    let cells: Vec<[String; 3]> = rows
+        .iter()
+        .map(|r| [r.folder.clone(), r.file.clone(), format_bytes(r.size)])
+        .collect();
+    let header = ["FOLDER", "FILE", "SIZE"].map(String::from);
+    let width = |col: usize| {
+        std::iter::once(&header)
+            .chain(&cells)
+            .map(|row| row[col].chars().count())
+            .max()
+            .unwrap_or(0)
+    };
+    let (w0, w1, w2) = (width(0), width(1), width(2));
+
+    let mut out = String::new();
+    let rule = ["-".repeat(w0), "-".repeat(w1), "-".repeat(w2)];
+    for [folder, file, size] in [header, rule].iter().chain(&cells) {
+        let _ = writeln!(out, "{:<w0$}  {:<w1$}  {:>w2$}", folder, file, size);
+    }
+    out
+}
+
+/// What the dry-run preview shows beyond the moves themselves.
+#[derive(Debug, Default)]
+struct PreviewOptions {
+    /// Why each entry goes where it does, keyed by displayed source; appended in brackets.
+    reasons: BTreeMap<String, String>,
+    /// Entry sizes keyed by displayed source, for `FolderOrder::Size`.
+    sizes: BTreeMap<String, u64>,
+    /// How many entries from each origin folder land in each target, as in
+    /// `RunReport::by_origin`; listed when any origin is not the base.
+    by_origin: BTreeMap<String, BTreeMap<String, usize>>,
+    /// List at most this many entries per folder, then an `... and N more` line; the summary
+    /// still counts everything.
+    limit: Option<usize>,
+    order: FolderOrder,
+    /// List each folder's entries in plan order (`sort_within_folder`) rather than by name.
+    keep_order: bool,
+}
+
+/// Render the dry-run tree and summary for `file_moves`.
+///
+/// Entries already at their destination (a redo no-op) are marked `(unchanged)`, and entries
+/// coming out of a different folder than the base show where they come from, so a redo reads
+/// as a diff against the current state. `preview` adds reasons, a per-folder limit and the
+/// folder order.
+fn render_dry_run_preview(
+    file_moves: &[(String, String)],
+    preview: &PreviewOptions,
+    theme: &Theme,
+) -> String {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+    let mut folders: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+    let mut unchanged = 0usize;
+    let mut folder_sizes: BTreeMap<String, u64> = BTreeMap::new();
+
+    for (src, dst) in file_moves {
+        let dst_path = Path::new(dst);
+        let folder = dst_path
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .to_string_lossy()
+            .to_string();
+        let file_name = dst_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        let src_parent = Path::new(src).parent();
+        let note = if src == dst {
+            unchanged += 1;
+            " (unchanged)".to_string()
+        } else if src_parent.and_then(|p| p.parent()) == dst_path.parent().and_then(|p| p.parent())
+        {
+            // Moving between sibling target folders: show where it comes from
+            let origin = src_parent
+                .and_then(|p| p.file_name())
+                .unwrap_or_default()
+                .to_string_lossy();
+            format!(" <- {}", origin)
+        } else {
+            String::new()
+        };
+        let note = match preview.reasons.get(src) {
+            Some(reason) => format!("{} [{}]", note, reason),
+            None => note,
+        };
+        *folder_sizes.entry(folder.clone()).or_default() +=
+            preview.sizes.get(src).copied().unwrap_or(0);
+        folders.entry(folder).or_default().push((file_name, note));
+    }
+
+    let _ = writeln!(out, ".");
+    let mut folder_names: Vec<_> = folders.keys().cloned().collect();
+    // Heaviest first; ties stay in name order
+    match preview.order {
+        FolderOrder::Name => {}
+        FolderOrder::Count => folder_names.sort_by_key(|f| std::cmp::Reverse(folders[f].len())),
+        FolderOrder::Size => folder_names.sort_by_key(|f| std::cmp::Reverse(folder_sizes[f])),
+    }
+    let last_folder_idx = folder_names.len().saturating_sub(1);
+
+    for (i, folder) in folder_names.iter().enumerate() {
+        let is_last_folder = i == last_folder_idx;
+        let prefix_folder = if is_last_folder {
+            &theme.last
+        } else {
+            &theme.branch
+        };
+        let indent = if is_last_folder {
+            &theme.blank
+        } else {
+            &theme.pipe
+        };
+
+        let folder_name = Path::new(folder)
+            .file_name()
+            .unwrap_or_else(|| std::ffi::OsStr::new(folder))
+            .to_string_lossy();
+
+        let _ = writeln!(
+            out,
+            "{}{}{}{}",
+            prefix_folder, theme.folder_start, folder_name, theme.folder_end
+        );
+
+        let mut files = folders.get(folder).unwrap().clone();
+        if !preview.keep_order {
+            files.sort();
+        }
+        let hidden = preview
+            .limit
+            .map_or(0, |limit| files.len().saturating_sub(limit));
+        files.truncate(files.len() - hidden);
+        let last_file_idx = files.len().saturating_sub(1);
+
+        for (j, (file, note)) in files.into_iter().enumerate() {
+            let prefix_file = if j == last_file_idx && hidden == 0 {
+                &theme.last
+            } else {
+                &theme.branch
+            };
+            let _ = writeln!(out, "{}{}{}{}", indent, prefix_file, file, note);
+        }
+        if hidden > 0 {
+            let _ = writeln!(out, "{}{}... and {} more", indent, theme.last, hidden);
+        }
+    }
+
+    // Optional: summary
+    let _ = writeln!(out, "\nSummary:");
+    let _ = writeln!(out, "  Total folders: {}", folders.len());
+    let total_files: usize = folders.values().map(|v| v.len()).sum();
+    let _ = writeln!(out, "  Total files:   {}", total_files);
+    if unchanged > 0 {
+        let _ = writeln!(out, "  Moves:         {}", total_files - unchanged);
+        let _ = writeln!(out, "  Unchanged:     {}", unchanged);
+    }
+    // On a redo, show where the files in each existing folder end up
+    if preview.by_origin.keys().any(|origin| origin != ".") {
+        let _ = writeln!(out, "  By origin:");
+        for (origin, targets) in &preview.by_origin {
+            let spread: Vec<String> = targets
+                .iter()
+                .map(|(target, n)| format!("{} -> {}", n, target))
+                .collect();
+            let _ = writeln!(out, "    {}: {}", origin, spread.join(", "));
+        }
+    }
+    let _ = writeln!(out, "  Mode:          dry-run (no changes made)");
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assign::partition;
+    use std::fs::{self, File};
+    use tempfile::tempdir;
+
+    thread_local! {
+        /// Records logged on this test's thread, as `(level, message)`.
+        static LOGGED: std::cell::RefCell<Vec<(log::Level, String)>> = Default::default();
+    }
+
+    /// Process-wide logger that hands each record to the logging thread's `LOGGED`, so
+    /// parallel tests only see their own records.
+    struct CapturingLogger;
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            LOGGED.with(|logged| {
+                logged
+                    .borrow_mut()
+                    .push((record.level(), record.args().to_string()))
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Install `CapturingLogger` (once per process) and take this thread's records so far.
+    fn take_logged() -> Vec<(log::Level, String)> {
+        static LOGGER: CapturingLogger = CapturingLogger;
+        static INSTALL: std::sync::Once = std::sync::Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&LOGGER).expect("no other logger in tests");
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+        LOGGED.with(|logged| logged.take())
+    }
+
+    #[test]
+    fn test_partition_even() {
+        let files: Vec<PathBuf> = (0..8).map(|i| PathBuf::from(format!("f{}", i))).collect();
+        let buckets = partition(files, 4, Remainder::First);
+        assert_eq!(buckets.len(), 4);
+        assert_eq!(
+            buckets.iter().map(|b| b.len()).collect::<Vec<_>>(),
+            vec![2, 2, 2, 2]
+        );
+    }
+
+    #[test]
+    fn test_partition_uneven() {
+        let files: Vec<PathBuf> = (0..10).map(|i| PathBuf::from(format!("f{}", i))).collect();
+        let buckets = partition(files, 3, Remainder::First);
+        assert_eq!(
+            buckets.iter().map(|b| b.len()).collect::<Vec<_>>(),
+            vec![4, 3, 3]
+        );
+    }
+
+    #[test]
+    fn test_format_folder_name_hex() {
+        let hex = |i, pad| folder_name("ex", i, SuffixStyle::Hex, "-", 1, pad).unwrap();
+        assert_eq!(hex(9, 0), "ex-a");
+        assert_eq!(hex(15, 0), "ex-10");
+        assert_eq!(hex(254, 0), "ex-ff");
+        assert_eq!(hex(9, 3), "ex-00a");
+        assert_eq!(Pad::Auto.width(255, SuffixStyle::Hex), 2);
+    }
+
+    #[test]
+    fn test_format_folder_name_letters() {
+        let letters = |i| folder_name("ex", i, SuffixStyle::Letters, "-", 1, 0).unwrap();
+        assert_eq!(letters(0), "ex-a");
+        assert_eq!(letters(25), "ex-z");
+        assert_eq!(letters(26), "ex-aa");
+    }
+
+    #[test]
+    fn integration_move_files() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        // create 5 files
+        for i in 0..5 {
+            let p = base.join(format!("file{}.txt", i));
+            File::create(&p)?;
+        }
+
+        // run - move into 3 buckets, force=true so that existing won't block (not needed here)
+        run(
+            base.to_str().unwrap(),
+            &RunOptions {
+                matching: "*.txt".to_string(),
+                subfolders: 3,
+                prefix: "pack".to_string(),
+                force: true,
+                ..Default::default()
+            },
+        )?;
+
+        // check folders
+        let a = base.join("pack-1");
+        let b = base.join("pack-2");
+        let c = base.join("pack-3");
+        assert!(a.is_dir() && b.is_dir() && c.is_dir());
+
+        let cnts = [
+            fs::read_dir(&a)?.count(),
+            fs::read_dir(&b)?.count(),
+            fs::read_dir(&c)?.count(),
+        ];
+        // distribution should sum to 5
+        assert_eq!(cnts.iter().sum::<usize>(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn redo_existing_folders() -> Result<()> {
+        // Test that files inside existing prefix-* folders are collected and re-shuffled
+        let dir = tempdir()?;
+        let base = dir.path();
+
+        // create two existing folders pack-1 and pack-2 with some files
+        let p1 = base.join("pack-1");
+        let p2 = base.join("pack-2");
+        fs::create_dir_all(&p1)?;
+        fs::create_dir_all(&p2)?;
+
+        File::create(p1.join("a.txt"))?;
+        File::create(p1.join("b.txt"))?;
+        File::create(p2.join("c.txt"))?;
+
+        // Now ask to re-split into 3 buckets
+        run(
+            base.to_str().unwrap(),
+            &RunOptions {
+                matching: "*.txt".to_string(),
+                subfolders: 3,
+                prefix: "pack".to_string(),
+                force: true,
+                ..Default::default()
+            },
+        )?;
+
+        // ensure pack-1..pack-3 exist and files moved
+        let p3 = base.join("pack-3");
+        assert!(p1.is_dir() && p2.is_dir() && p3.is_dir());
+        let total: usize = [p1, p2, p3]
+            .iter()
+            .map(|d| fs::read_dir(d).unwrap().count())
+            .sum();
+        assert_eq!(total, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn distribute_directories() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+
+        // three project folders, each with a file that must travel with its folder
+        for name in ["proj-a", "proj-b", "proj-c"] {
+            fs::create_dir_all(base.join(name))?;
+            File::create(base.join(name).join("README.txt"))?;
+        }
+        // a loose file that dirs mode must leave alone
+        File::create(base.join("loose.txt"))?;
+
+        run(
+            base.to_str().unwrap(),
+            &RunOptions {
+                subfolders: 2,
+                prefix: "pack".to_string(),
+                entries: Entries::Dirs,
+                ..Default::default()
+            },
+        )?;
+
+        assert!(base.join("pack-1/proj-a/README.txt").is_file());
+        assert!(base.join("pack-1/proj-b/README.txt").is_file());
+        assert!(base.join("pack-2/proj-c/README.txt").is_file());
+        assert!(base.join("loose.txt").is_file());
+        assert_eq!(fs::read_dir(base.join("pack-1"))?.count(), 2);
+        assert_eq!(fs::read_dir(base.join("pack-2"))?.count(), 1);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn strict_mode_fails_on_unreadable_directory() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir()?;
+        let base = dir.path();
+        File::create(base.join("a.txt"))?;
+        let locked = base.join("locked");
+        fs::create_dir_all(&locked)?;
+        File::create(locked.join("b.txt"))?;
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000))?;
+
+        // Privileged users can read the directory anyway; nothing to trigger then.
+        if fs::read_dir(&locked).is_ok() {
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o755))?;
+            return Ok(());
+        }
+
+        let mut opts = RunOptions {
+            matching: "*.txt".to_string(),
+            recursive: true,
+            dry_run: true,
+            ..Default::default()
+        };
+        let report = run(base.to_str().unwrap(), &opts);
+
+        opts.strict = true;
+        let strict = run(base.to_str().unwrap(), &opts);
+
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755))?;
+
+        assert_eq!(report?.walk_errors.len(), 1);
+        assert!(strict.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn quiet_errors_still_counts_skipped_entries() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir()?;
+        let base = dir.path();
+        File::create(base.join("a.txt"))?;
+        let locked = base.join("locked");
+        fs::create_dir_all(&locked)?;
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000))?;
+
+        // Privileged users can read the directory anyway; nothing to trigger then.
+        if fs::read_dir(&locked).is_ok() {
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o755))?;
+            return Ok(());
+        }
+
+        let opts = RunOptions {
+            matching: "*.txt".to_string(),
+            recursive: true,
+            dry_run: true,
+            quiet_errors: true,
+            ..Default::default()
+        };
+        let report = run(base.to_str().unwrap(), &opts);
+
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755))?;
+
+        let report = report?;
+        assert_eq!(report.walk_errors.len(), 1);
+        assert_eq!(report.moved, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn group_by_hash_prefix() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        fs::write(base.join("one.txt"), "hello")?;
+        fs::write(base.join("two.txt"), "world")?;
+
+        run(
+            base.to_str().unwrap(),
+            &RunOptions {
+                matching: "*.txt".to_string(),
+                prefix: "pack".to_string(),
+                by: GroupBy::Hash,
+                ..Default::default()
+            },
+        )?;
+
+        // sha256("hello") = 2cf24dba..., sha256("world") = 486ea462...
+        assert!(base.join("pack-2c/one.txt").is_file());
+        assert!(base.join("pack-48/two.txt").is_file());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parallel_walk_matches_sequential() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for sub in ["a", "a/b", "c", "c/d/e"] {
+            fs::create_dir_all(base.join(sub))?;
+            for i in 0..5 {
+                File::create(base.join(sub).join(format!("f{}.txt", i)))?;
+                File::create(base.join(sub).join(format!("f{}.log", i)))?;
+            }
+        }
+        File::create(base.join("top.TXT"))?;
+
+        let mut opts = RunOptions {
+            matching: "*.txt".to_string(),
+            recursive: true,
+            ..Default::default()
+        };
+        let sequential = collect_files(base, &opts)?.files;
+        opts.threads = Some(4);
+        let parallel = collect_files(base, &opts)?.files;
+
+        assert_eq!(sequential.len(), 21);
+        assert_eq!(sequential, parallel);
+
+        Ok(())
+    }
+
+    #[test]
+    fn ext_shorthand_filters_extensions() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for name in ["a.txt", "b.MD", "c.jpg", "d.md.bak", "e"] {
+            File::create(base.join(name))?;
+        }
+
+        let opts = RunOptions {
+            ext: vec!["txt".to_string(), ".md".to_string()],
+            ..Default::default()
+        };
+        let names: Vec<String> = collect_files(base, &opts)?
+            .files
+            .iter()
+            .map(|p| p.path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, vec!["a.txt", "b.MD"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn one_per_folder_gives_each_file_a_folder() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for i in 0..7 {
+            File::create(base.join(format!("file{}.txt", i)))?;
+        }
+
+        let report = run(
+            base.to_str().unwrap(),
+            &RunOptions {
+                matching: "*.txt".to_string(),
+                one_per_folder: true,
+                ..Default::default()
+            },
+        )?;
+
+        assert_eq!(report.folders, 7);
+        for i in 1..=7 {
+            assert_eq!(fs::read_dir(base.join(format!("group-{}", i)))?.count(), 1);
+        }
+        assert!(!base.join("group-8").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn output_dir_inside_moved_directory_is_rejected() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        fs::create_dir_all(base.join("out"))?;
+        fs::create_dir_all(base.join("other"))?;
+
+        let err = plan(
+            base.to_str().unwrap(),
+            &RunOptions {
+                entries: Entries::Dirs,
+                output_dir: Some(base.join("out")),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+
+        let msg = err.to_string();
+        assert!(msg.contains("Cannot move"), "{}", msg);
+        assert!(msg.contains("out"), "{}", msg);
+        // nothing was touched
+        assert!(base.join("other").is_dir());
+
+        Ok(())
+    }
+
+    #[test]
+    fn suffix_none_rejects_several_folders() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for i in 0..4 {
+            File::create(base.join(format!("file{}.txt", i)))?;
+        }
+        let mut opts = RunOptions {
+            subfolders: 3,
+            suffix: SuffixStyle::None,
+            ..Default::default()
+        };
+
+        let err = run(base.to_str().unwrap(), &opts).unwrap_err();
+        assert!(err.to_string().contains("--suffix none"), "{}", err);
+        assert!(base.join("file0.txt").is_file());
+
+        opts.subfolders = 1;
+        run(base.to_str().unwrap(), &opts)?;
+        assert_eq!(fs::read_dir(base.join("group"))?.count(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn suffix_none_rejects_split_keyed_groups() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for name in ["a.jpg", "b.jpg", "c.jpg", "d.png", "e.png"] {
+            File::create(base.join(name))?;
+        }
+        let split = RunOptions {
+            by: GroupBy::Extension,
+            subfolders: 2,
+            suffix: SuffixStyle::None,
+            ..Default::default()
+        };
+        let err = run(base.to_str().unwrap(), &split).unwrap_err();
+        assert!(err.to_string().contains("--suffix none"), "{}", err);
+
+        let by_index = RunOptions {
+            by: GroupBy::Extension,
+            folder_name_from: Some(FolderNameFrom::Index),
+            suffix: SuffixStyle::None,
+            ..Default::default()
+        };
+        let err = run(base.to_str().unwrap(), &by_index).unwrap_err();
+        assert!(err.to_string().contains("--suffix none"), "{}", err);
+        assert!(base.join("a.jpg").is_file());
+
+        Ok(())
+    }
+
+    #[test]
+    fn report_counts_bytes_moved() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        let mut total = 0;
+        for i in 0..4 {
+            let body = "x".repeat(100 * (i + 1));
+            fs::write(base.join(format!("file{}.txt", i)), &body)?;
+            total += body.len() as u64;
+        }
+
+        let report = run(
+            base.to_str().unwrap(),
+            &RunOptions {
+                subfolders: 2,
+                ..Default::default()
+            },
+        )?;
+
+        assert_eq!(report.bytes_moved, total);
+        assert_eq!(format_bytes(total), "1000 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+
+        Ok(())
+    }
+
+    #[test]
+    fn locked_errors_are_skippable() {
+        use std::io::{Error, ErrorKind};
+
+        let busy = anyhow::Error::new(Error::from(ErrorKind::PermissionDenied))
+            .context("Failed copying a to b");
+        assert!(is_locked_error(&busy));
+
+        let missing = anyhow::Error::new(Error::from(ErrorKind::NotFound));
+        assert!(!is_locked_error(&missing));
+        assert!(!is_locked_error(&anyhow!("Destination file exists")));
+    }
+
+    #[test]
+    fn redo_honours_exclude_and_hidden() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        let pack = base.join("pack-1");
+        fs::create_dir_all(&pack)?;
+        File::create(pack.join("keep.txt"))?;
+        File::create(pack.join("scratch.tmp"))?;
+        File::create(pack.join(".hidden"))?;
+        File::create(base.join("a.txt"))?;
+
+        let report = run(
+            base.to_str().unwrap(),
+            &RunOptions {
+                subfolders: 2,
+                prefix: "pack".to_string(),
+                exclude: vec!["*.tmp".to_string()],
+                skip_hidden: true,
+                ..Default::default()
+            },
+        )?;
+
+        // a.txt and keep.txt are the only entries; the filtered ones stay where they were
+        assert_eq!(
+            report
+                .by_origin
+                .values()
+                .flat_map(|t| t.values())
+                .sum::<usize>(),
+            2
+        );
+        assert!(pack.join("scratch.tmp").is_file());
+        assert!(pack.join(".hidden").is_file());
+        assert!(!base.join("pack-2/scratch.tmp").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn move_threshold_keeps_balanced_layout() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        // Balanced, but not the way a fresh round-robin split would deal them
+        for (i, name) in ["a", "b", "c", "d", "e", "f", "g", "h", "i"]
+            .iter()
+            .enumerate()
+        {
+            let pack = base.join(format!("pack-{}", i % 3 + 1));
+            fs::create_dir_all(&pack)?;
+            File::create(pack.join(format!("{}.txt", name)))?;
+        }
+        let mut opts = RunOptions {
+            subfolders: 3,
+            prefix: "pack".to_string(),
+            strategy: Strategy::RoundRobin,
+            dry_run: true,
+            ..Default::default()
+        };
+        assert!(run(base.to_str().unwrap(), &opts)?.moved > 0);
+
+        opts.move_threshold = Some(10.0);
+        opts.dry_run = false;
+        let report = run(base.to_str().unwrap(), &opts)?;
+        assert_eq!(report.moved, 0);
+        assert!(base.join("pack-2/b.txt").is_file());
+
+        // A lopsided layout is still redistributed
+        fs::rename(base.join("pack-2/b.txt"), base.join("pack-1/b.txt"))?;
+        fs::rename(base.join("pack-2/e.txt"), base.join("pack-1/e.txt"))?;
+        assert!(run(base.to_str().unwrap(), &opts)?.moved > 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn redo_dedups_many_files() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for pack in 1..=4 {
+            let folder = base.join(format!("pack-{}", pack));
+            fs::create_dir_all(&folder)?;
+            for i in 0..500 {
+                File::create(folder.join(format!("f{}-{}.txt", pack, i)))?;
+            }
+        }
+
+        // --recursive reaches the same files through the main walk and the redo walk
+        let collected = collect_files(
+            base,
+            &RunOptions {
+                matching: "**/*.txt".to_string(),
+                recursive: true,
+                prefix: "pack".to_string(),
+                ..Default::default()
+            },
+        )?;
+
+        assert_eq!(collected.files.len(), 2000);
+        let unique: HashSet<&PathBuf> = collected.files.iter().map(|f| &f.path).collect();
+        assert_eq!(unique.len(), 2000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn extension_groups_named_by_key() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for name in ["a.JPG", "b.jpg", "c.png", "README"] {
+            File::create(base.join(name))?;
+        }
+        let mut opts = RunOptions {
+            by: GroupBy::Extension,
+            folder_name_from: Some(FolderNameFrom::Key),
+            dry_run: true,
+            ..Default::default()
+        };
+
+        let folders = |opts: &RunOptions| -> Result<Vec<String>> {
+            Ok(plan(base.to_str().unwrap(), opts)?
+                .folders
+                .iter()
+                .map(|f| f.path.file_name().unwrap().to_string_lossy().to_string())
+                .collect())
+        };
+
+        assert_eq!(folders(&opts)?, vec!["jpg", "no-extension", "png"]);
+        opts.folder_name_from = None;
+        assert_eq!(
+            folders(&opts)?,
+            vec!["group-jpg", "group-no-extension", "group-png"]
+        );
+        opts.folder_name_from = Some(FolderNameFrom::Index);
+        assert_eq!(folders(&opts)?, vec!["group-1", "group-2", "group-3"]);
+
+        opts.dry_run = false;
+        opts.folder_name_from = Some(FolderNameFrom::Key);
+        run(base.to_str().unwrap(), &opts)?;
+        assert!(base.join("jpg/a.JPG").is_file());
+        assert!(base.join("jpg/b.jpg").is_file());
+
+        Ok(())
+    }
+
+    #[test]
+    fn max_folders_guards_derived_count() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for i in 0..50 {
+            File::create(base.join(format!("file{:02}.txt", i)))?;
+        }
+        let mut opts = RunOptions {
+            max_per_folder: Some(2),
+            max_folders: Some(10),
+            ..Default::default()
+        };
+
+        let err = run(base.to_str().unwrap(), &opts).unwrap_err();
+        assert!(err.to_string().contains("25 folders"), "{}", err);
+        assert!(!base.join("group-1").exists());
+
+        opts.max_per_folder = Some(5);
+        let report = run(base.to_str().unwrap(), &opts)?;
+        assert_eq!(report.folders, 10);
+        assert_eq!(fs::read_dir(base.join("group-10"))?.count(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn update_replaces_only_older_destinations() -> Result<()> {
+        use std::time::{Duration, SystemTime};
+
+        let dir = tempdir()?;
+        let base = dir.path();
+        // Destinations outside the base, so they are not collected as redo sources
+        let pack = base.join("out/group-1");
+        fs::create_dir_all(&pack)?;
+        let old = SystemTime::now() - Duration::from_secs(3600);
+        let set_mtime = |path: &Path, time| -> Result<()> {
+            File::options().write(true).open(path)?.set_modified(time)?;
+            Ok(())
+        };
+
+        // Older source than destination: left in place
+        fs::write(base.join("stale.txt"), "source")?;
+        fs::write(pack.join("stale.txt"), "dest")?;
+        set_mtime(&base.join("stale.txt"), old)?;
+        // Newer source than destination: overwrites
+        fs::write(base.join("fresh.txt"), "source")?;
+        fs::write(pack.join("fresh.txt"), "dest")?;
+        set_mtime(&pack.join("fresh.txt"), old)?;
+
+        let report = run(
+            base.to_str().unwrap(),
+            &RunOptions {
+                matching: "*.txt".to_string(),
+                output_dir: Some(base.join("out")),
+                update: true,
+                ..Default::default()
+            },
+        )?;
+
+        assert_eq!(report.skipped, 1);
+        assert_eq!(fs::read_to_string(pack.join("stale.txt"))?, "dest");
+        assert!(base.join("stale.txt").is_file());
+        assert_eq!(fs::read_to_string(pack.join("fresh.txt"))?, "source");
+        assert!(!base.join("fresh.txt").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_stable_after_fresh_run() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for i in 0..10 {
+            File::create(base.join(format!("file{}.txt", i)))?;
+        }
+        let mut opts = RunOptions {
+            subfolders: 3,
+            prefix: "pack".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(check_stable(base.to_str().unwrap(), &opts)?.len(), 10);
+        run(base.to_str().unwrap(), &opts)?;
+        assert!(check_stable(base.to_str().unwrap(), &opts)?.is_empty());
+
+        opts.subfolders = 4;
+        assert!(!check_stable(base.to_str().unwrap(), &opts)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn pad_width_is_fixed_or_derived() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for i in 0..12 {
+            File::create(base.join(format!("file{:02}.txt", i)))?;
+        }
+        let names = |opts: &RunOptions| -> Result<Vec<String>> {
+            Ok(plan(base.to_str().unwrap(), opts)?
+                .folders
+                .iter()
+                .map(|f| f.path.file_name().unwrap().to_string_lossy().to_string())
+                .collect())
+        };
+        let mut opts = RunOptions {
+            subfolders: 5,
+            prefix: "pack".to_string(),
+            pad: Pad::Width(3),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            names(&opts)?,
+            vec!["pack-001", "pack-002", "pack-003", "pack-004", "pack-005"]
+        );
+
+        opts.pad = Pad::Auto;
+        assert_eq!(names(&opts)?[0], "pack-1");
+        opts.subfolders = 12;
+        assert_eq!(names(&opts)?[0], "pack-01");
+        assert_eq!(names(&opts)?[11], "pack-12");
+
+        Ok(())
+    }
+
+    #[test]
+    fn normalize_unicode_merges_nfc_and_nfd() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        File::create(base.join("caf\u{e9}.txt"))?;
+        File::create(base.join("cafe\u{301}.txt"))?;
+        let mut opts = RunOptions {
+            on_collision: OnCollision::Rename,
+            ..Default::default()
+        };
+
+        // Byte-wise distinct names keep their own destinations
+        let dests = |opts: &RunOptions| -> Result<Vec<String>> {
+            Ok(plan(base.to_str().unwrap(), opts)?
+                .moves()
+                .map(|m| m.dest.file_name().unwrap().to_string_lossy().to_string())
+                .collect())
+        };
+        assert_eq!(dests(&opts)?.len(), 2);
+        assert_ne!(dests(&opts)?[0], dests(&opts)?[1]);
+
+        // Under NFC both are "café.txt", so the second one is renamed
+        opts.normalize_unicode = Some(UnicodeForm::Nfc);
+        let mut names = dests(&opts)?;
+        names.sort();
+        assert_eq!(names, vec!["caf\u{e9}-1.txt", "caf\u{e9}.txt"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn since_run_only_takes_new_files() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path().join("files");
+        fs::create_dir_all(&base)?;
+        for i in 0..4 {
+            File::create(base.join(format!("old{}.txt", i)))?;
+        }
+        let mut opts = RunOptions {
+            subfolders: 2,
+            journal: Some(dir.path().join("journal.csv")),
+            ..Default::default()
+        };
+        run(base.to_str().unwrap(), &opts)?;
+
+        // Make sure the new file's mtime is clearly after the recorded run
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        File::create(base.join("new.txt"))?;
+
+        opts.since_run = true;
+        let plan = plan(base.to_str().unwrap(), &opts)?;
+        let sources: Vec<_> = plan.moves().map(|m| m.src.clone()).collect();
+        assert_eq!(sources, vec![fs::canonicalize(base.join("new.txt"))?]);
+
+        let report = run(base.to_str().unwrap(), &opts)?;
+        assert_eq!(report.moved, 1);
+        assert!(base.join("group-1/new.txt").is_file());
+        assert!(base.join("group-2/old3.txt").is_file());
+
+        Ok(())
+    }
+
+    #[test]
+    fn template_count_matches_bucket_sizes() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for i in 0..7 {
+            File::create(base.join(format!("file{}.txt", i)))?;
+        }
+
+        let report = run(
+            base.to_str().unwrap(),
+            &RunOptions {
+                subfolders: 3,
+                prefix: "batch".to_string(),
+                template: Some("{prefix}-{index}-[{count}files]".to_string()),
+                ..Default::default()
+            },
+        )?;
+
+        assert_eq!(report.folders, 3);
+        for (name, count) in [
+            ("batch-1-[3files]", 3),
+            ("batch-2-[2files]", 2),
+            ("batch-3-[2files]", 2),
+        ] {
+            assert_eq!(fs::read_dir(base.join(name))?.count(), count, "{}", name);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn strict_paths_rejects_escaping_destinations() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path().join("files");
+        fs::create_dir_all(&base)?;
+        File::create(base.join("a.txt"))?;
+        let mut opts = RunOptions {
+            template: Some("../{prefix}-{index}".to_string()),
+            strict_paths: true,
+            ..Default::default()
+        };
+
+        let err = run(base.to_str().unwrap(), &opts).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("1 destinations escape"), "{}", msg);
+        assert!(msg.contains("a.txt"), "{}", msg);
+        assert!(base.join("a.txt").is_file());
+
+        opts.template = Some("{prefix}-{index}".to_string());
+        run(base.to_str().unwrap(), &opts)?;
+        assert!(base.join("group-1/a.txt").is_file());
+
+        Ok(())
+    }
+
+    #[test]
+    fn extension_groups_split_per_extension() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for i in 0..9 {
+            File::create(base.join(format!("photo{}.jpg", i)))?;
+        }
+        for i in 0..2 {
+            File::create(base.join(format!("shot{}.png", i)))?;
+        }
+
+        let plan = plan(
+            base.to_str().unwrap(),
+            &RunOptions {
+                by: GroupBy::Extension,
+                subfolders: 3,
+                folder_name_from: Some(FolderNameFrom::Key),
+                ..Default::default()
+            },
+        )?;
+
+        let sizes: Vec<(String, usize)> = plan
+            .folders
+            .iter()
+            .map(|f| {
+                let name = f.path.file_name().unwrap().to_string_lossy().to_string();
+                (name, f.moves.len())
+            })
+            .collect();
+        assert_eq!(
+            sizes,
+            vec![
+                ("jpg-1".to_string(), 3),
+                ("jpg-2".to_string(), 3),
+                ("jpg-3".to_string(), 3),
+                ("png-1".to_string(), 1),
+                ("png-2".to_string(), 1),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn auto_prefix_uses_common_name() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for i in 1..=12 {
+            File::create(base.join(format!("IMG_{:04}.jpg", i)))?;
+        }
+        let opts = RunOptions {
+            subfolders: 2,
+            auto_prefix: true,
+            ..Default::default()
+        };
+
+        run(base.to_str().unwrap(), &opts)?;
+        assert!(base.join("IMG-1/IMG_0001.jpg").is_file());
+        assert!(base.join("IMG-2/IMG_0012.jpg").is_file());
+
+        // The derived prefix also finds the folders again on a redo
+        assert!(check_stable(base.to_str().unwrap(), &opts)?.is_empty());
+        assert_eq!(
+            common_name_prefix(&[PathBuf::from("a.txt"), PathBuf::from("b.txt")]),
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn refolderignore_excludes_patterns() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        fs::write(base.join(IGNORE_FILE), "# logs stay put\n*.log\n")?;
+        for name in ["a.txt", "b.txt", "c.log", "d.tmp"] {
+            File::create(base.join(name))?;
+        }
+        let opts = RunOptions {
+            subfolders: 1,
+            exclude: vec!["*.tmp".to_string()],
+            ..Default::default()
+        };
+
+        run(base.to_str().unwrap(), &opts)?;
+        assert!(base.join("group-1/a.txt").is_file());
+        assert!(base.join("group-1/b.txt").is_file());
+        assert!(base.join("c.log").is_file());
+        assert!(base.join("d.tmp").is_file());
+        assert!(base.join(IGNORE_FILE).is_file());
+
+        Ok(())
+    }
+
+    #[test]
+    fn dry_run_preview_writes_to_buffer() -> Result<()> {
+        let moves = vec![
+            ("b/a.txt".to_string(), "b/group-1/a.txt".to_string()),
+            ("b/c.txt".to_string(), "b/group-2/c.txt".to_string()),
+            ("b/b.txt".to_string(), "b/group-1/b.txt".to_string()),
+        ];
+        let mut out = Vec::new();
+        write_dry_run_preview(&mut out, &moves, &Theme::default())?;
+
+        let text = String::from_utf8(out)?;
+        let tree = format!(
+            ".\n├── {b}group-1{e}\n│   ├── a.txt\n│   └── b.txt\n└── {b}group-2{e}\n    └── c.txt\n",
+            b = "\x1b[1;34m",
+            e = "\x1b[0m"
+        );
+        assert!(text.starts_with(&tree), "{}", text);
+        assert!(text.contains("  Total files:   3\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn prune_dir_skips_subtree() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for i in 0..50 {
+            let sub = base.join(format!("node_modules/pkg{}/lib", i));
+            fs::create_dir_all(&sub)?;
+            File::create(sub.join("index.js"))?;
+        }
+        fs::create_dir_all(base.join("src/node_modules_docs"))?;
+        File::create(base.join("src/app.js"))?;
+        File::create(base.join("src/node_modules_docs/readme.js"))?;
+
+        let mut opts = RunOptions {
+            matching: "*.js".to_string(),
+            recursive: true,
+            prune_dir: vec!["node_modules".to_string()],
+            ..Default::default()
+        };
+        let sequential = collect_files(base, &opts)?.files;
+        opts.threads = Some(4);
+        let parallel = collect_files(base, &opts)?.files;
+
+        let names: Vec<_> = sequential
+            .iter()
+            .map(|p| p.path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, vec!["app.js", "readme.js"]);
+        assert_eq!(sequential, parallel);
+
+        Ok(())
+    }
+
+    #[test]
+    fn relative_output_has_no_absolute_paths() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            File::create(base.join(name))?;
+        }
+        let opts = RunOptions {
+            matching: "*.txt".to_string(),
+            subfolders: 2,
+            relative_output: true,
+            manifest: Some(base.join("moves.csv")),
+            ..Default::default()
+        };
+        let base_str = base.to_str().unwrap();
+
+        let shown = plan(base_str, &opts)?.relative_to(&fs::canonicalize(base)?);
+        let preview = render_dry_run_preview(
+            &shown.display_pairs(),
+            &PreviewOptions::default(),
+            &Theme::unicode(),
+        );
+        assert!(preview.lines().all(|l| !l.contains('/')), "{}", preview);
+        assert_eq!(
+            shown.folders[0].moves[0].dest,
+            PathBuf::from("group-1/a.txt")
+        );
+
+        run(base_str, &opts)?;
+        let manifest = fs::read_to_string(base.join("moves.csv"))?;
+        assert!(manifest.contains("\na.txt,group-1/a.txt\n"), "{}", manifest);
+        assert!(verify(base_str, &opts)?.is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn collapse_single_creates_no_folder() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        fs::create_dir(base.join("pack-1"))?;
+        File::create(base.join("pack-1/c.txt"))?;
+        for name in ["a.txt", "b.txt"] {
+            File::create(base.join(name))?;
+        }
+        let opts = RunOptions {
+            prefix: "pack".to_string(),
+            max_per_folder: Some(10),
+            collapse_single: true,
+            ..Default::default()
+        };
+
+        let report = run(base.to_str().unwrap(), &opts)?;
+        assert_eq!(report.moved, 1);
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            assert!(base.join(name).is_file());
+        }
+        assert!(!base.join("pack-1/c.txt").exists());
+        assert!(!base.join("pack-2").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mapping_pins_entries() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        let map_dir = tempdir()?;
+        let map = map_dir.path().join("pins.txt");
+        fs::write(&map, "# curated\nb.txt -> special/\ne.txt -> special\n")?;
+        for name in ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt", "f.txt"] {
+            File::create(base.join(name))?;
+        }
+        let mut opts = RunOptions {
+            subfolders: 2,
+            mapping: Some(map.clone()),
+            ..Default::default()
+        };
+
+        run(base.to_str().unwrap(), &opts)?;
+        for path in [
+            "special/b.txt",
+            "special/e.txt",
+            "group-1/a.txt",
+            "group-1/c.txt",
+            "group-2/d.txt",
+            "group-2/f.txt",
+        ] {
+            assert!(base.join(path).is_file(), "{}", path);
+        }
+
+        // Every pinned name has to be among the matched entries
+        fs::write(&map, "missing.txt -> special\n")?;
+        opts.dry_run = true;
+        let err = plan(base.to_str().unwrap(), &opts).unwrap_err();
+        assert!(err.to_string().contains("missing.txt"), "{}", err);
+
+        Ok(())
+    }
+
+    #[test]
+    fn timestamp_folder_wraps_targets() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for name in ["a.txt", "b.txt"] {
+            File::create(base.join(name))?;
+        }
+        let opts = RunOptions {
+            subfolders: 2,
+            timestamp_folder: Some("%Y-%m-%dT%H-%M".to_string()),
+            ..Default::default()
+        };
+
+        run(base.to_str().unwrap(), &opts)?;
+        let parents: Vec<PathBuf> = fs::read_dir(base)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<std::io::Result<_>>()?;
+        assert_eq!(parents.len(), 1);
+        let name = parents[0]
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        let pattern = regex::Regex::new(r"^\d{4}-\d{2}-\d{2}T\d{2}-\d{2}$").unwrap();
+        assert!(pattern.is_match(&name), "{}", name);
+        assert!(parents[0].join("group-1/a.txt").is_file());
+        assert!(parents[0].join("group-2/b.txt").is_file());
+
+        let june = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_717_243_200);
+        assert_eq!(
+            naming::format_timestamp("%Y-%m-%dT%H-%M-%S %%", june)?,
+            "2024-06-01T12-00-00 %"
+        );
+        assert!(naming::format_timestamp("%Q", june).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cancel_stops_between_moves() -> Result<()> {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let dir = tempdir()?;
+        let base = dir.path();
+        for i in 0..6 {
+            File::create(base.join(format!("f{}.txt", i)))?;
+        }
+        let cancel = Arc::new(AtomicBool::new(false));
+        let opts = RunOptions {
+            subfolders: 2,
+            manifest: Some(base.join("moves.csv")),
+            cancel: Some(cancel.clone()),
+            ..Default::default()
+        };
+
+        let mut moves = 0;
+        let report = run_with_progress(base.to_str().unwrap(), &opts, |_| {
+            moves += 1;
+            if moves == 2 {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        })?;
+        assert!(report.cancelled);
+        assert!(report.is_partial());
+        assert_eq!((report.moved, report.skipped), (2, 4));
+        assert!(base.join("group-1/f0.txt").is_file());
+        assert!(base.join("f2.txt").is_file());
+        assert!(base.join("f5.txt").is_file());
+        assert!(!base.join("moves.csv").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn by_parent_groups_by_directory_name() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for album in ["artist/album1", "artist/album2"] {
+            fs::create_dir_all(base.join(album))?;
+            for track in ["01.mp3", "02.mp3"] {
+                File::create(base.join(album).join(format!("{}-{}", &album[7..], track)))?;
+            }
+        }
+        File::create(base.join("loose.mp3"))?;
+        let opts = RunOptions {
+            matching: "*.mp3".to_string(),
+            recursive: true,
+            by: GroupBy::Parent,
+            ..Default::default()
+        };
+
+        run(base.to_str().unwrap(), &opts)?;
+        assert!(base.join("group-album1/album1-01.mp3").is_file());
+        assert!(base.join("group-album1/album1-02.mp3").is_file());
+        assert!(base.join("group-album2/album2-01.mp3").is_file());
+        assert!(base.join("group-root/loose.mp3").is_file());
+
+        // The target folders keep their key on a redo
+        assert!(check_stable(base.to_str().unwrap(), &opts)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn unique_names_across_folders() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for sub in ["a", "b", "c"] {
+            fs::create_dir(base.join(sub))?;
+            File::create(base.join(sub).join("photo.jpg"))?;
+            File::create(base.join(sub).join(format!("{}.jpg", sub)))?;
+        }
+        File::create(base.join("photo-1.jpg"))?;
+        let opts = RunOptions {
+            matching: "*.jpg".to_string(),
+            recursive: true,
+            subfolders: 3,
+            unique_names: true,
+            ..Default::default()
+        };
+
+        let plan = plan(base.to_str().unwrap(), &opts)?;
+        let names: Vec<_> = plan.moves().map(|m| m.dest.file_name().unwrap()).collect();
+        let distinct: HashSet<_> = names.iter().collect();
+        assert_eq!(names.len(), 7);
+        assert_eq!(distinct.len(), 7, "{:?}", names);
+        assert!(names.iter().any(|n| *n == "photo-2.jpg"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn explain_annotates_preview() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for i in 0..6 {
+            File::create(base.join(format!("f{}.txt", i)))?;
+        }
+        let opts = RunOptions {
+            subfolders: 3,
+            strategy: Strategy::RoundRobin,
+            explain: true,
+            dry_run: true,
+            ..Default::default()
+        };
+
+        let plan = plan(base.to_str().unwrap(), &opts)?;
+        let preview = render_dry_run_preview(
+            &plan.display_pairs(),
+            &PreviewOptions {
+                reasons: plan.display_reasons(),
+                ..Default::default()
+            },
+            &Theme::unicode(),
+        );
+        assert!(
+            preview.contains("f5.txt [bucket 2 (round-robin i=5 → 5%3)]"),
+            "{}",
+            preview
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn mirror_skips_names_in_reference() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        let reference = tempdir()?;
+        fs::create_dir(reference.path().join("group-1"))?;
+        File::create(reference.path().join("a.txt"))?;
+        File::create(reference.path().join("group-1/b.txt"))?;
+        for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+            File::create(base.join(name))?;
+        }
+        let opts = RunOptions {
+            subfolders: 2,
+            mirror: Some(reference.path().to_path_buf()),
+            ..Default::default()
+        };
+
+        let report = run(base.to_str().unwrap(), &opts)?;
+        assert_eq!(report.moved, 2);
+        assert!(base.join("group-1/c.txt").is_file());
+        assert!(base.join("group-2/d.txt").is_file());
+        assert!(base.join("a.txt").is_file());
+        assert!(base.join("b.txt").is_file());
+
+        Ok(())
+    }
+
+    #[test]
+    fn labels_name_folders() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for i in 0..8 {
+            File::create(base.join(format!("f{}.txt", i)))?;
+        }
+        let mut opts = RunOptions {
+            subfolders: 4,
+            labels: ["Q1", "Q2", "Q3", "Q4"].map(String::from).to_vec(),
+            dry_run: true,
+            ..Default::default()
+        };
+
+        let names = |opts: &RunOptions| -> Result<Vec<String>> {
+            Ok(plan(base.to_str().unwrap(), opts)?
+                .folders
+                .iter()
+                .map(|f| f.path.file_name().unwrap().to_string_lossy().to_string())
+                .collect())
+        };
+        assert_eq!(names(&opts)?, ["Q1", "Q2", "Q3", "Q4"]);
+
+        opts.subfolders = 5;
+        assert!(names(&opts).is_err());
+        opts.cycle_labels = true;
+        assert_eq!(names(&opts)?, ["Q1", "Q2", "Q3", "Q4", "Q1-2"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn empty_match_policy() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path().to_str().unwrap();
+        File::create(dir.path().join("a.txt"))?;
+        let mut opts = RunOptions {
+            matching: "*.jpg".to_string(),
+            subfolders: 2,
+            ..Default::default()
+        };
+
+        assert_eq!(run(base, &opts)?.matched, 0);
+
+        opts.on_empty_match = EmptyMatch::Error;
+        let err = run(base, &opts).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RefolderError>(),
+            Some(&RefolderError::NoMatches)
+        );
+        assert!(dir.path().join("a.txt").is_file());
+
+        Ok(())
+    }
+
+    #[test]
+    fn a_run_stats_each_entry_once() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        fs::create_dir(base.join("group-1"))?;
+        for (i, name) in ["a.txt", "b.txt", "c.log", "group-1/d.txt", "group-1/e.txt"]
+            .iter()
+            .enumerate()
+        {
+            fs::write(base.join(name), "x".repeat(i * 10))?;
+        }
+        let opts = RunOptions {
+            matching: "**/*".to_string(),
+            recursive: true,
+            subfolders: 2,
+            ext: vec!["txt".to_string()],
+            strategy: Strategy::Size,
+            collate: true,
+            sort_key: Some(r"(\w)\.".to_string()),
+            explain: true,
+            ..Default::default()
+        };
+
+        let stats: std::cell::RefCell<HashMap<PathBuf, usize>> = Default::default();
+        let counting = |path: PathBuf| {
+            *stats.borrow_mut().entry(path.clone()).or_default() += 1;
+            CollectedFile::stat(path)
+        };
+        let plan = plan_with(base.to_str().unwrap(), &opts, &counting)?;
+        assert_eq!(plan.moves().count(), 4);
+        let stats = stats.into_inner();
+        assert!(stats.len() >= 5, "{:?}", stats);
+        assert!(stats.values().all(|&n| n == 1), "{:?}", stats);
+        // The sizes the --strategy size split used are the ones carried into the plan
+        for m in plan.moves() {
+            assert_eq!(m.size, fs::metadata(&m.src)?.len());
+        }
+
+        // Grown behind the run's back: a move that stats again would count the new byte
+        for m in plan.moves() {
+            std::io::Write::write_all(&mut File::options().append(true).open(&m.src)?, b"x")?;
+        }
+        let planned: u64 = plan.moves().filter(|m| !m.is_noop()).map(|m| m.size).sum();
+        let report = execute(
+            base.to_str().unwrap(),
+            &opts,
+            plan,
+            std::time::SystemTime::now(),
+            |_| {},
+        )?;
+        assert!(report.moved > 0);
+        assert_eq!(report.bytes_moved, planned);
+
+        Ok(())
+    }
+
+    #[test]
+    fn preview_limit_truncates_folders() {
+        let moves: Vec<(String, String)> = (0..100)
+            .map(|i| {
+                (
+                    format!("b/f{:03}.txt", i),
+                    format!("b/group-1/f{:03}.txt", i),
+                )
+            })
+            .collect();
+
+        let preview = render_dry_run_preview(
+            &moves,
+            &PreviewOptions {
+                limit: Some(5),
+                ..Default::default()
+            },
+            &Theme::ascii(),
+        );
+        assert!(
+            preview.contains("|-- f004.txt\n    `-- ... and 95 more\n"),
+            "{}",
+            preview
+        );
+        assert!(!preview.contains("f005.txt"));
+        assert!(preview.contains("  Total files:   100\n"));
+    }
+
+    #[test]
+    fn continue_numbering_starts_after_existing_folders() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        for folder in ["pack-1", "pack-2"] {
+            fs::create_dir(base.join(folder))?;
+            fs::write(base.join(folder).join(format!("{}.txt", folder)), "old")?;
+        }
+        for i in 0..4 {
+            fs::write(base.join(format!("new{}.txt", i)), "new")?;
+        }
+
+        let opts = RunOptions {
+            matching: "*.txt".into(),
+            subfolders: 2,
+            prefix: "pack".into(),
+            continue_numbering: true,
+            ..Default::default()
+        };
+        run(base.to_str().unwrap(), &opts)?;
+
+        assert!(base.join("pack-1/pack-1.txt").exists());
+        assert!(base.join("pack-2/pack-2.txt").exists());
+        assert_eq!(fs::read_dir(base.join("pack-1"))?.count(), 1);
+        assert!(base.join("pack-3/new0.txt").exists());
+        assert!(base.join("pack-4/new2.txt").exists());
+        assert!(!base.join("pack-5").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn moves_are_logged_at_debug_level() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        for i in 0..3 {
+            fs::write(base.join(format!("f{}.txt", i)), "x")?;
+        }
+        take_logged();
+
+        let opts = RunOptions {
+            matching: "*.txt".into(),
+            subfolders: 3,
+            ..Default::default()
+        };
+        run(base.to_str().unwrap(), &opts)?;
+
+        let moves: Vec<String> = take_logged()
+            .into_iter()
+            .filter(|(level, _)| *level == log::Level::Debug)
+            .map(|(_, message)| message)
+            .filter(|message| message.starts_with("moved "))
+            .collect();
+        assert_eq!(moves.len(), 3, "{:?}", moves);
+        let dest = base.canonicalize()?.join("group-2").join("f1.txt");
+        assert!(
+            moves
+                .iter()
+                .any(|m| m.ends_with(&format!(" -> {}", dest.display()))),
+            "{:?}",
+            moves
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn regex_match_type_filters_file_names() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        for name in [
+            "IMG_001.jpg",
+            "IMG_42.jpg",
+            "IMG_x.jpg",
+            "IMG_001.jpg.bak",
+            "img_7.jpg",
+        ] {
+            fs::write(base.join(name), "x")?;
+        }
+
+        let opts = RunOptions {
+            matching: r"^IMG_\d+\.jpg$".into(),
+            match_type: MatchType::Regex,
+            ignore_case: false,
+            subfolders: 1,
+            ..Default::default()
+        };
+        let regex_plan = plan(base.to_str().unwrap(), &opts)?;
+        let names: Vec<String> = regex_plan
+            .moves()
+            .map(|m| m.src.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, ["IMG_001.jpg", "IMG_42.jpg"]);
+
+        let literal = RunOptions {
+            matching: "IMG_x.jpg".into(),
+            match_type: MatchType::Literal,
+            ..opts
+        };
+        let literal_plan = plan(base.to_str().unwrap(), &literal)?;
+        assert_eq!(literal_plan.moves().count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn stage_leaves_only_final_folders() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        for i in 0..6 {
+            fs::write(base.join(format!("f{}.txt", i)), "x")?;
+        }
+
+        let opts = RunOptions {
+            matching: "*.txt".into(),
+            subfolders: 3,
+            prefix: "pack".into(),
+            stage: true,
+            ..Default::default()
+        };
+        let report = run(base.to_str().unwrap(), &opts)?;
+        assert_eq!(report.moved, 6);
+
+        let mut names: Vec<String> = fs::read_dir(base)?
+            .map(|e| e.map(|e| e.file_name().to_string_lossy().to_string()))
+            .collect::<std::io::Result<_>>()?;
+        names.sort();
+        assert_eq!(names, ["pack-1", "pack-2", "pack-3"]);
+        assert!(base.join("pack-2/f2.txt").is_file());
+
+        // A leftover staging directory is never reused
+        fs::create_dir(base.join(".pack-4.tmp"))?;
+        let redo = RunOptions {
+            subfolders: 4,
+            ..opts
+        };
+        let err = run(base.to_str().unwrap(), &redo).unwrap_err();
+        assert!(err.to_string().contains(".pack-4.tmp"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn jsonl_writes_one_move_per_line() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        for i in 0..7 {
+            fs::write(base.join(format!("f\"{}\".txt", i)), "x")?;
+        }
+        let opts = RunOptions {
+            matching: "*.txt".into(),
+            subfolders: 3,
+            ..Default::default()
+        };
+
+        let plan = plan(base.to_str().unwrap(), &opts)?;
+        let mut out = Vec::new();
+        write_jsonl(&mut out, &plan)?;
+        let text = String::from_utf8(out)?;
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 7);
+        for (line, m) in lines.iter().zip(plan.moves()) {
+            let value: serde_json::Value = serde_json::from_str(line)?;
+            let object = value.as_object().expect("a JSON object per line");
+            assert_eq!(object.len(), 2);
+            assert_eq!(object["src"], m.src.to_str().unwrap());
+            assert_eq!(object["dest"], m.dest.to_str().unwrap());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn max_bytes_adds_a_folder_for_oversized_files() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        for (name, size) in [("a.bin", 10), ("b.bin", 500), ("c.bin", 10), ("d.bin", 10)] {
+            fs::write(base.join(name), vec![0u8; size])?;
+        }
+
+        let opts = RunOptions {
+            matching: "*.bin".into(),
+            subfolders: 2,
+            max_bytes: Some(100),
+            ..Default::default()
+        };
+        run(base.to_str().unwrap(), &opts)?;
+
+        assert!(base.join("group-1/a.bin").is_file());
+        assert!(base.join("group-2/c.bin").is_file());
+        assert!(base.join("group-2/d.bin").is_file());
+        assert!(base.join("group-3/b.bin").is_file());
+        assert_eq!(fs::read_dir(base.join("group-1"))?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn negated_patterns_drop_files() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        for name in ["a.txt", "b.tmp", "c.jpg", "d.TMP"] {
+            fs::write(base.join(name), "x")?;
+        }
+        let names = |opts: &RunOptions| -> Result<Vec<String>> {
+            Ok(plan(base.to_str().unwrap(), opts)?
+                .moves()
+                .map(|m| m.src.file_name().unwrap().to_string_lossy().to_string())
+                .collect())
+        };
+
+        let opts = RunOptions {
+            matching: "*".into(),
+            extra_patterns: vec!["!*.tmp".into()],
+            subfolders: 1,
+            ..Default::default()
+        };
+        assert_eq!(names(&opts)?, ["a.txt", "c.jpg"]);
+
+        // Negations alone start from everything
+        let only_negations = RunOptions {
+            matching: "!*.tmp".into(),
+            extra_patterns: vec!["!*.jpg".into()],
+            ..opts
+        };
+        assert_eq!(names(&only_negations)?, ["a.txt"]);
+        Ok(())
+    }
+
+    #[test]
+    fn skip_identical_content_leaves_duplicates() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        fs::create_dir(base.join("group-1"))?;
+        fs::write(base.join("group-1/kept.dat"), "same")?;
+        fs::write(base.join("copy.txt"), "same")?;
+        fs::write(base.join("other.txt"), "diff")?;
+
+        let opts = RunOptions {
+            matching: "*.txt".into(),
+            subfolders: 1,
+            skip_identical_content: true,
+            ..Default::default()
+        };
+        let report = run(base.to_str().unwrap(), &opts)?;
+
+        assert_eq!(report.skipped, 1);
+        assert!(base.join("copy.txt").is_file());
+        assert!(!base.join("group-1/copy.txt").exists());
+        assert!(base.join("group-1/other.txt").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn custom_theme_draws_the_preview() -> Result<()> {
+        let moves = vec![
+            ("b/a.txt".to_string(), "b/group-1/a.txt".to_string()),
+            ("b/b.txt".to_string(), "b/group-1/b.txt".to_string()),
+            ("b/c.txt".to_string(), "b/group-2/c.txt".to_string()),
+        ];
+        let theme = Theme {
+            folder_start: "<".into(),
+            folder_end: ">".into(),
+            branch: "+ ".into(),
+            last: "\\ ".into(),
+            pipe: ": ".into(),
+            blank: "  ".into(),
+        };
+        let mut out = Vec::new();
+        write_dry_run_preview(&mut out, &moves, &theme)?;
+
+        let text = String::from_utf8(out)?;
+        assert!(
+            text.starts_with(".\n+ <group-1>\n: + a.txt\n: \\ b.txt\n\\ <group-2>\n  \\ c.txt\n"),
+            "{}",
+            text
+        );
+
+        let plain = render_dry_run_preview(
+            &moves,
+            &PreviewOptions::default(),
+            &Theme::ascii().without_color(),
+        );
+        assert!(plain.contains("|-- group-1\n"), "{}", plain);
+        Ok(())
+    }
+
+    #[test]
+    fn group_unmatched_sweeps_the_rest() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        for name in [
+            "a.txt", "b.txt", "c.txt", "d.txt", "x.jpg", "y.md", "skip.log",
+        ] {
+            fs::write(base.join(name), "x")?;
+        }
+
+        let opts = RunOptions {
+            matching: "*.txt".into(),
+            subfolders: 2,
+            exclude: vec!["*.log".into()],
+            group_unmatched: Some("other".into()),
+            ..Default::default()
+        };
+        let report = run(base.to_str().unwrap(), &opts)?;
+
+        assert_eq!(report.matched, 4);
+        assert!(base.join("group-1/a.txt").is_file());
+        assert!(base.join("group-1/b.txt").is_file());
+        assert!(base.join("group-2/c.txt").is_file());
+        assert!(base.join("group-2/d.txt").is_file());
+        assert!(base.join("other/x.jpg").is_file());
+        assert!(base.join("other/y.md").is_file());
+        assert_eq!(fs::read_dir(base.join("other"))?.count(), 2);
+        // Excluded entries stay loose
+        assert!(base.join("skip.log").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn group_unmatched_sweeps_when_nothing_matches() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        fs::write(base.join("x.jpg"), "x")?;
+        fs::write(base.join("y.md"), "x")?;
+
+        let report = run(
+            base.to_str().unwrap(),
+            &RunOptions {
+                matching: "*.txt".into(),
+                subfolders: 2,
+                group_unmatched: Some("other".into()),
+                on_empty_match: EmptyMatch::Error,
+                ..Default::default()
+            },
+        )?;
+
+        assert_eq!((report.matched, report.moved), (0, 2));
+        assert!(base.join("other/x.jpg").is_file());
+        assert!(base.join("other/y.md").is_file());
+        assert!(!base.join("group-1").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn vanished_sources_are_skipped() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        for i in 0..4 {
+            fs::write(base.join(format!("f{}.txt", i)), "x")?;
+        }
+        let opts = RunOptions {
+            matching: "*.txt".into(),
+            subfolders: 2,
+            ..Default::default()
+        };
+
+        // Another process deletes f3.txt while the run is under way
+        let doomed = base.canonicalize()?.join("f3.txt");
+        let report = run_with_progress(base.to_str().unwrap(), &opts, |_| {
+            let _ = fs::remove_file(&doomed);
+        })?;
+
+        assert_eq!(report.moved, 3);
+        assert_eq!(report.skipped, 1);
+        assert!(report.is_partial());
+        assert!(base.join("group-2/f2.txt").is_file());
+        assert!(!base.join("group-2/f3.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn sort_folders_by_size_lists_heaviest_first() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        for (name, size) in [
+            ("a", 10),
+            ("b", 20),
+            ("c", 5000),
+            ("d", 30),
+            ("e", 1),
+            ("f", 2),
+        ] {
+            fs::write(base.join(name), vec![0u8; size])?;
+        }
+        let opts = RunOptions {
+            subfolders: 3,
+            ..Default::default()
+        };
+        let plan = plan(base.to_str().unwrap(), &opts)?;
+        let sizes = plan
+            .moves()
+            .map(|m| {
+                (
+                    m.src.display().to_string(),
+                    fs::metadata(&m.src).unwrap().len(),
+                )
+            })
+            .collect();
+        let preview = PreviewOptions {
+            sizes,
+            order: FolderOrder::Size,
+            ..Default::default()
+        };
+
+        let text = render_dry_run_preview(
+            &plan.display_pairs(),
+            &preview,
+            &Theme::ascii().without_color(),
+        );
+        let folders: Vec<&str> = text
+            .lines()
+            .filter_map(|l| l.strip_prefix("|-- ").or_else(|| l.strip_prefix("`-- ")))
+            .collect();
+        // group-2 holds c (5000 B), group-3 holds e and f (3 B)
+        assert_eq!(folders, ["group-2", "group-1", "group-3"]);
+        Ok(())
+    }
+
+    #[test]
+    fn table_aligns_columns() {
+        let rows = [
+            TableRow {
+                folder: "group-1".into(),
+                file: "a.txt".into(),
+                size: 12,
+            },
+            TableRow {
+                folder: "group-10".into(),
+                file: "longer-name.txt".into(),
+                size: 2048,
+            },
+        ];
+        let table = render_table(&rows);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines[0], "FOLDER    FILE                SIZE");
+        assert_eq!(lines[1], "--------  ---------------  -------");
+        assert_eq!(lines[2], "group-1   a.txt               12 B");
+        assert_eq!(lines[3], "group-10  longer-name.txt  2.0 KiB");
+    }
+
+    #[test]
+    fn strip_prefix_sorts_but_keeps_names() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        for name in ["DSC_zeta.jpg", "beta.jpg", "DSC_alpha.jpg"] {
+            fs::write(base.join(name), "x")?;
+        }
+
+        let opts = RunOptions {
+            subfolders: 3,
+            strip_prefix: Some("DSC_".into()),
+            ..Default::default()
+        };
+        run(base.to_str().unwrap(), &opts)?;
+
+        assert!(base.join("group-1/DSC_alpha.jpg").is_file());
+        assert!(base.join("group-2/beta.jpg").is_file());
+        assert!(base.join("group-3/DSC_zeta.jpg").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn into_fills_existing_folders() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        for dir in ["inbox", "archive", "review"] {
+            fs::create_dir(base.join(dir))?;
+        }
+        for i in 1..=6 {
+            fs::write(base.join(format!("f{}.txt", i)), "x")?;
+        }
+
+        let mut opts = RunOptions {
+            matching: "*.txt".into(),
+            into: ["inbox", "archive", "review", "later"]
+                .map(String::from)
+                .to_vec(),
+            ..Default::default()
+        };
+        let err = run(base.to_str().unwrap(), &opts).unwrap_err();
+        assert!(err.to_string().contains("later"), "{}", err);
+
+        opts.into.pop();
+        run(base.to_str().unwrap(), &opts)?;
+        for (dir, files) in [
+            ("inbox", ["f1.txt", "f2.txt"]),
+            ("archive", ["f3.txt", "f4.txt"]),
+            ("review", ["f5.txt", "f6.txt"]),
+        ] {
+            for file in files {
+                assert!(base.join(dir).join(file).is_file(), "{}/{}", dir, file);
+            }
+        }
+        assert!(!base.join("group-1").exists());
+
+        fs::write(base.join("f7.txt"), "x")?;
+        opts.into = vec!["later".into()];
+        opts.create_missing = true;
+        run(base.to_str().unwrap(), &opts)?;
+        assert!(base.join("later/f7.txt").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn min_files_refuses_small_runs() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        for i in 1..=3 {
+            fs::write(base.join(format!("f{}.txt", i)), "x")?;
+        }
+
+        let mut opts = RunOptions {
+            min_files: Some(10),
+            ..Default::default()
+        };
+        let err = run(base.to_str().unwrap(), &opts).unwrap_err();
+        assert!(err.to_string().contains("--min-files 10"), "{}", err);
+        assert!(base.join("f1.txt").is_file());
+
+        opts.min_files = Some(3);
+        run(base.to_str().unwrap(), &opts)?;
+        assert!(base.join("group-1/f1.txt").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn prefer_origin_keeps_files_in_place() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        // A balanced earlier run, but with the folders in the opposite order to the sort key
+        for (dir, files) in [
+            ("group-1", ["f3.txt", "f4.txt"]),
+            ("group-2", ["f1.txt", "f2.txt"]),
+        ] {
+            fs::create_dir(base.join(dir))?;
+            for file in files {
+                fs::write(base.join(dir).join(file), "x")?;
+            }
+        }
+
+        let opts = RunOptions {
+            subfolders: 2,
+            sort_key: Some(r"f(\d+)".into()),
+            prefer_origin: true,
+            ..Default::default()
+        };
+        let moving = |opts: &RunOptions| -> Result<usize> {
+            let plan = plan(base.to_str().unwrap(), opts)?;
+            Ok(plan
+                .folders
+                .iter()
+                .flat_map(|f| &f.moves)
+                .filter(|m| !m.is_noop())
+                .count())
+        };
+        assert_eq!(moving(&opts)?, 0);
+        // Without the option the two folders swap their contents
+        let numbered = RunOptions {
+            prefer_origin: false,
+            ..opts.clone()
+        };
+        assert_eq!(moving(&numbered)?, 4);
+
+        // New entries still move; the pairing only follows what is already in place
+        fs::write(base.join("f5.txt"), "x")?;
+        run(base.to_str().unwrap(), &opts)?;
+        assert!(base.join("group-2/f1.txt").is_file());
+        assert!(base.join("group-2/f3.txt").is_file());
+        assert!(base.join("group-1/f4.txt").is_file());
+        assert!(base.join("group-1/f5.txt").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn checksum_manifest_records_digests() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            fs::write(base.join(name), name)?;
+        }
+        let opts = RunOptions {
+            matching: "*.txt".into(),
+            subfolders: 2,
+            manifest: Some(base.join("moves.csv")),
+            checksum_manifest: true,
+            ..Default::default()
+        };
+        run(base.to_str().unwrap(), &opts)?;
+
+        let rows = manifest::parse_rows(&fs::read_to_string(base.join("moves.csv"))?);
+        assert_eq!(rows[0], ["source", "destination", "sha256"]);
+        assert_eq!(rows.len(), 4);
+        for row in &rows[1..] {
+            let digest = &row[2];
+            assert_eq!(digest.len(), 64, "{:?}", row);
+            assert!(digest.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')));
+            assert_eq!(*digest, hash::sha256_hex(Path::new(&row[1]))?);
+        }
+        // Still a manifest `verify` understands
+        assert!(verify(base.to_str().unwrap(), &opts)?.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn checksum_manifest_leaves_entries_kept_in_place_without_digest() -> Result<()> {
+        use std::time::{Duration, SystemTime};
+
+        for move_mode in [MoveMode::Sequential, MoveMode::Parallel] {
+            let tmp = tempdir()?;
+            let base = tmp.path();
+            let pack = base.join("out/group-1");
+            fs::create_dir_all(&pack)?;
+            // Older than what already sits at its destination, so `update` leaves it
+            fs::write(base.join("a.txt"), "source")?;
+            fs::write(pack.join("a.txt"), "unrelated")?;
+            File::options()
+                .write(true)
+                .open(base.join("a.txt"))?
+                .set_modified(SystemTime::now() - Duration::from_secs(3600))?;
+            fs::write(base.join("b.txt"), "moves")?;
+
+            let opts = RunOptions {
+                matching: "*.txt".into(),
+                output_dir: Some(base.join("out")),
+                update: true,
+                manifest: Some(tmp.path().join("moves.csv")),
+                checksum_manifest: true,
+                move_mode,
+                ..Default::default()
+            };
+            let report = run(base.to_str().unwrap(), &opts)?;
+            assert_eq!((report.moved, report.skipped), (1, 1));
+
+            let rows = manifest::parse_rows(&fs::read_to_string(tmp.path().join("moves.csv"))?);
+            let digest = |name: &str| {
+                rows.iter()
+                    .find(|row| row[0].ends_with(name))
+                    .map(|row| row[2].clone())
+                    .unwrap()
+            };
+            assert_eq!(digest("a.txt"), "");
+            assert_eq!(digest("b.txt"), hash::sha256_hex(&pack.join("b.txt"))?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn replace_existing_folders_removes_stale_empties() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        for i in 0..10 {
+            fs::write(base.join(format!("f{}.txt", i)), "x")?;
+        }
+        let mut opts = RunOptions {
+            prefix: "pack".into(),
+            subfolders: 5,
+            ..Default::default()
+        };
+        run(base.to_str().unwrap(), &opts)?;
+        // Not part of the naming scheme, so kept even though empty
+        fs::create_dir(base.join("pack-extra"))?;
+
+        opts.subfolders = 3;
+        opts.replace_existing_folders = true;
+        run(base.to_str().unwrap(), &opts)?;
+        for i in 1..=3 {
+            assert!(base.join(format!("pack-{}", i)).is_dir());
+        }
+        assert!(!base.join("pack-4").exists());
+        assert!(!base.join("pack-5").exists());
+        assert!(base.join("pack-extra").is_dir());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replace_existing_folders_uses_numbers_without_canonical_paths() -> Result<()> {
+        let tmp = tempdir()?;
+        let real = tmp.path().join("real");
+        fs::create_dir(&real)?;
+        let link = tmp.path().join("link");
+        std::os::unix::fs::symlink(&real, &link)?;
+        for name in ["a.txt", "b.txt"] {
+            fs::write(real.join(name), "x")?;
+        }
+        // pack-3 is within the new count even though it stays empty; pack-4 is above it
+        fs::create_dir(real.join("pack-3"))?;
+        fs::create_dir(real.join("pack-4"))?;
+
+        let opts = RunOptions {
+            prefix: "pack".into(),
+            subfolders: 3,
+            replace_existing_folders: true,
+            no_canonicalize: true,
+            ..Default::default()
+        };
+        run(link.to_str().unwrap(), &opts)?;
+        assert!(real.join("pack-3").is_dir());
+        assert!(!real.join("pack-4").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn exclude_dir_name_protects_folders() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        // One plain folder and one that looks like a target folder to the redo collection
+        for (dir, file) in [("_originals", "a.txt"), ("group-originals", "b.txt")] {
+            fs::create_dir(base.join(dir))?;
+            fs::write(base.join(dir).join(file), "x")?;
+        }
+        for name in ["c.txt", "d.txt"] {
+            fs::write(base.join(name), "x")?;
+        }
+
+        let opts = RunOptions {
+            matching: "*.txt".into(),
+            subfolders: 2,
+            recursive: true,
+            exclude_dir_name: vec!["_originals".into(), "group-originals".into()],
+            ..Default::default()
+        };
+        run(base.to_str().unwrap(), &opts)?;
+        assert!(base.join("_originals/a.txt").is_file());
+        assert!(base.join("group-originals/b.txt").is_file());
+        assert!(base.join("group-1/c.txt").is_file());
+        assert!(base.join("group-2/d.txt").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn flatten_gathers_folders_into_base() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        let names = ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"];
+        for name in names {
+            fs::write(base.join(name), name)?;
+        }
+        let mut opts = RunOptions {
+            matching: "*.txt".into(),
+            prefix: "pack".into(),
+            subfolders: 3,
+            ..Default::default()
+        };
+        run(base.to_str().unwrap(), &opts)?;
+        assert!(base.join("pack-3/e.txt").is_file());
+        // Already back in the base: the one in pack-1 collides with it
+        fs::write(base.join("a.txt"), "other")?;
+        // Empty before the flatten, so not the flatten's to remove
+        fs::create_dir(base.join("pack-9"))?;
+
+        opts.on_collision = OnCollision::Rename;
+        // Options that rename entries are not applied on the way back
+        opts.rename_template = Some("{folder}_{index}.{ext}".into());
+        opts.rename_case = RenameCase::Kebab;
+        let report = flatten(base.to_str().unwrap(), &opts, true)?;
+        assert_eq!(report.moved, 5);
+        for name in names {
+            assert!(base.join(name).is_file(), "{}", name);
+        }
+        assert_eq!(fs::read_to_string(base.join("a-1.txt"))?, "a.txt");
+        for i in 1..=3 {
+            assert!(!base.join(format!("pack-{}", i)).exists());
+        }
+        assert!(base.join("pack-9").is_dir());
+        Ok(())
+    }
+
+    #[test]
+    fn parallel_moves_match_sequential() -> Result<()> {
+        fn layout(base: &Path) -> Result<Vec<PathBuf>> {
+            let mut files: Vec<PathBuf> = walkdir(base)?
+                .into_iter()
+                .map(|p| p.strip_prefix(base).unwrap().to_path_buf())
+                .collect();
+            files.sort();
+            Ok(files)
+        }
+        fn walkdir(dir: &Path) -> Result<Vec<PathBuf>> {
+            let mut found = Vec::new();
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    found.extend(walkdir(&path)?);
+                }
+                found.push(path);
+            }
+            Ok(found)
+        }
+
+        let (sequential, parallel) = (tempdir()?, tempdir()?);
+        for base in [sequential.path(), parallel.path()] {
+            for i in 0..600 {
+                fs::write(base.join(format!("f{:03}.dat", i)), vec![0u8; i % 7])?;
+            }
+        }
+        let opts = RunOptions {
+            subfolders: 7,
+            ..Default::default()
+        };
+        let name = |m: &PlannedMove| m.src.file_name().unwrap().to_owned();
+        let mut in_order = Vec::new();
+        let report = run_with_progress(sequential.path().to_str().unwrap(), &opts, |m| {
+            in_order.push(name(m))
+        })?;
+        let mut reported = Vec::new();
+        let piped = run_with_progress(
+            parallel.path().to_str().unwrap(),
+            &RunOptions {
+                move_mode: MoveMode::Parallel,
+                threads: Some(4),
+                ..opts
+            },
+            |m| reported.push(name(m)),
+        )?;
+
+        assert_eq!(layout(parallel.path())?, layout(sequential.path())?);
+        // Progress arrives in plan order, not in the order the workers finish
+        assert_eq!(reported, in_order);
+        assert_eq!(
+            (piped.moved, piped.folders, piped.bytes_moved),
+            (report.moved, report.folders, report.bytes_moved)
+        );
+        assert_eq!(piped.by_origin, report.by_origin);
+        Ok(())
+    }
+
+    #[test]
+    fn report_json_matches_the_run() -> Result<()> {
+        let tmp = tempdir()?;
+        let base = tmp.path();
+        for i in 0..5 {
+            fs::write(base.join(format!("f{}.txt", i)), "abc")?;
+        }
+        fs::create_dir(base.join("group-1"))?;
+        let opts = RunOptions {
+            subfolders: 2,
+            ..Default::default()
+        };
+        let report = run(base.to_str().unwrap(), &opts)?;
+
+        let parsed: serde_json::Value = serde_json::from_str(&report.to_json())?;
+        assert_eq!(parsed["matched"], 5);
+        assert_eq!(parsed["folders"], 2);
+        assert_eq!(parsed["moved"], 5);
+        assert_eq!(parsed["skipped"], 0);
+        assert_eq!(parsed["unreadable"], 0);
+        assert_eq!(parsed["bytes_moved"], 15);
+        assert_eq!(parsed["cancelled"], false);
+        // group-1 was already there
+        let created = fs::canonicalize(base.join("group-2"))?;
+        assert_eq!(parsed["created"], serde_json::json!([created]));
+        let in_folders: usize = (1..=2)
+            .map(|i| fs::read_dir(base.join(format!("group-{}", i))).map(|d| d.count()))
+            .sum::<std::io::Result<usize>>()?;
+        assert_eq!(parsed["moved"], in_folders);
+        Ok(())
+    }
+
+    #[test]
+    fn resume_finishes_an_interrupted_run() -> Result<()> {
+        let (tmp, journal_dir) = (tempdir()?, tempdir()?);
+        let base = tmp.path();
+        for i in 0..6 {
+            fs::write(base.join(format!("f{}.txt", i)), "x")?;
+        }
+        let opts = RunOptions {
+            subfolders: 2,
+            journal: Some(journal_dir.path().join("journal.csv")),
+            ..Default::default()
+        };
+
+        // A run killed after three of its six moves
+        let planned = plan(base.to_str().unwrap(), &opts)?;
+        let mut journal = journal::Writer::begin(
+            opts.journal.as_ref().unwrap(),
+            std::time::SystemTime::now(),
+            &planned,
+        )?;
+        for m in planned.moves().take(3) {
+            fs::create_dir_all(m.dest.parent().unwrap())?;
+            fs::rename(&m.src, &m.dest)?;
+            journal.record(m)?;
+        }
+        drop(journal);
+
+        let report = resume(base.to_str().unwrap(), &opts)?;
+        assert_eq!(report.moved, 3);
+        for m in planned.moves() {
+            assert!(m.dest.is_file(), "{}", m.dest.display());
+        }
+        // The resumed run recorded its own moves, so there is nothing left to do
+        assert_eq!(resume(base.to_str().unwrap(), &opts)?.moved, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn folder_is_not_created_when_none_of_its_entries_move() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+            File::create(base.join(name))?;
+        }
+        let opts = RunOptions {
+            subfolders: 2,
+            prefix: "pack".to_string(),
+            ..Default::default()
+        };
+
+        // Everything bound for pack-2 disappears once the run is under way
+        let report = run_with_progress(base.to_str().unwrap(), &opts, |_| {
+            let _ = fs::remove_file(base.join("c.txt"));
+            let _ = fs::remove_file(base.join("d.txt"));
+        })?;
+
+        assert_eq!(report.moved, 2);
+        assert_eq!(report.skipped, 2);
+        assert!(base.join("pack-1/a.txt").is_file());
+        assert!(!base.join("pack-2").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn sort_within_folder_orders_the_manifest() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for (name, size) in [("a.txt", 3), ("b.txt", 10), ("c.txt", 1)] {
+            fs::write(base.join(name), "x".repeat(size))?;
+        }
+        let opts = RunOptions {
+            subfolders: 1,
+            matching: "*.txt".to_string(),
+            manifest: Some(base.join("moves.csv")),
+            sort_within_folder: Some(EntryOrder::Size),
+            ..Default::default()
+        };
+
+        // The preview lists the folder in the same order
+        let preview = run(
+            base.to_str().unwrap(),
+            &RunOptions {
+                dry_run: true,
+                capture_preview: true,
+                ..opts.clone()
+            },
+        )?
+        .preview;
+        let at = |name: &str| preview.find(name).unwrap();
+        assert!(
+            at("b.txt") < at("a.txt") && at("a.txt") < at("c.txt"),
+            "{}",
+            preview
+        );
+
+        run(base.to_str().unwrap(), &opts)?;
+
+        let manifest = fs::read_to_string(base.join("moves.csv"))?;
+        let names: Vec<_> = manifest
+            .lines()
+            .skip(1)
+            .map(|row| row.rsplit(['/', '\\']).next().unwrap())
+            .collect();
+        assert_eq!(names, ["b.txt", "a.txt", "c.txt"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn group_map_makes_one_folder_per_key() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path().join("photos");
+        fs::create_dir(&base)?;
+        for name in ["a.jpg", "b.jpg", "c.jpg", "d.jpg", "e.jpg"] {
+            File::create(base.join(name))?;
+        }
+        let csv = dir.path().join("labels.csv");
+        fs::write(
+            &csv,
+            "file,key\na.jpg,cat\nb.jpg,dog\nc.jpg,cat\nd.jpg,dog\n",
+        )?;
+
+        let report = run(
+            base.to_str().unwrap(),
+            &RunOptions {
+                group_map: Some(csv.clone()),
+                ..Default::default()
+            },
+        )?;
+
+        assert_eq!((report.folders, report.moved, report.skipped), (2, 4, 1));
+        assert!(base.join("group-cat/a.jpg").is_file());
+        assert!(base.join("group-cat/c.jpg").is_file());
+        assert!(base.join("group-dog/b.jpg").is_file());
+        assert!(base.join("group-dog/d.jpg").is_file());
+        assert!(base.join("e.jpg").is_file());
+
+        // The same map as JSON
+        let json = dir.path().join("labels.json");
+        fs::write(
+            &json,
+            r#"{"a.jpg": "cat", "b.jpg": "dog", "c.jpg": "cat", "d.jpg": "dog"}"#,
+        )?;
+        assert_eq!(mapping::read_groups(&json)?, mapping::read_groups(&csv)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn relative_manifest_matches_golden_file() -> Result<()> {
+        let golden = include_str!("../tests/golden/manifest.csv");
+        // Two trees with the same entries, created in a different order, walked differently
+        for (names, threads) in [
+            (["c.txt", "a.txt", "e.txt", "b.txt", "d.txt"], None),
+            (["e.txt", "d.txt", "b.txt", "c.txt", "a.txt"], Some(4)),
+        ] {
+            let dir = tempdir()?;
+            let base = dir.path().join("inbox");
+            fs::create_dir_all(base.join("pack-2"))?;
+            // A leftover from an earlier run, picked up as a redo source
+            File::create(base.join("pack-2/f.txt"))?;
+            for name in names {
+                File::create(base.join(name))?;
+            }
+            run(
+                base.to_str().unwrap(),
+                &RunOptions {
+                    subfolders: 2,
+                    prefix: "pack".to_string(),
+                    relative_output: true,
+                    manifest: Some(dir.path().join("moves.csv")),
+                    threads,
+                    ..Default::default()
+                },
+            )?;
+            assert_eq!(fs::read_to_string(dir.path().join("moves.csv"))?, golden);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn group_map_covering_no_file_is_not_an_empty_match() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path().join("photos");
+        fs::create_dir(&base)?;
+        File::create(base.join("a.jpg"))?;
+        File::create(base.join("b.jpg"))?;
+        let csv = dir.path().join("labels.csv");
+        fs::write(&csv, "x.jpg,cat\n")?;
+
+        let report = run(
+            base.to_str().unwrap(),
+            &RunOptions {
+                group_map: Some(csv),
+                on_empty_match: EmptyMatch::Error,
+                ..Default::default()
+            },
+        )?;
+
+        assert_eq!((report.matched, report.moved, report.skipped), (2, 0, 2));
+        assert!(report.is_partial());
+        assert!(base.join("a.jpg").is_file());
+
+        Ok(())
+    }
+
+    #[test]
+    fn verify_intact_distribution() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path().join("files");
+        fs::create_dir_all(&base)?;
+        for i in 0..5 {
+            File::create(base.join(format!("file{}.txt", i)))?;
+        }
+        let opts = RunOptions {
+            matching: "*.txt".to_string(),
+            subfolders: 3,
+            prefix: "pack".to_string(),
+            manifest: Some(dir.path().join("manifest.csv")),
+            ..Default::default()
+        };
+
+        run(base.to_str().unwrap(), &opts)?;
+        let report = verify(base.to_str().unwrap(), &opts)?;
+        assert!(report.is_ok(), "{:?}", report.discrepancies);
+
+        Ok(())
+    }
+
+    #[test]
+    fn verify_detects_deleted_file() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path().join("files");
+        fs::create_dir_all(&base)?;
+        for i in 0..5 {
+            File::create(base.join(format!("file{}.txt", i)))?;
+        }
+        let opts = RunOptions {
+            matching: "*.txt".to_string(),
+            subfolders: 3,
+            prefix: "pack".to_string(),
+            manifest: Some(dir.path().join("manifest.csv")),
+            ..Default::default()
+        };
+
+        run(base.to_str().unwrap(), &opts)?;
+        // pack-2 holds file2 and file3; the remaining four still split evenly, so only the
+        // manifest can tell that file3 went missing.
+        fs::remove_file(base.join("pack-2/file3.txt"))?;
+
+        let report = verify(base.to_str().unwrap(), &opts)?;
+        assert!(!report.is_ok());
+        assert!(report.discrepancies[0].contains("file3.txt"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rename_case_kebab() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        File::create(base.join("My File.TXT"))?;
+        // normalizes onto the same name as the first file
+        File::create(base.join("my  file.txt"))?;
+
+        run(
+            base.to_str().unwrap(),
+            &RunOptions {
+                prefix: "pack".to_string(),
+                rename_case: RenameCase::Kebab,
+                on_collision: OnCollision::Rename,
+                ..Default::default()
+            },
+        )?;
+
+        assert!(base.join("pack-1/my-file.txt").is_file());
+        assert!(base.join("pack-1/my-file-1.txt").is_file());
+        assert!(!base.join("My File.TXT").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn rename_template_numbers_files_within_each_folder() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for name in ["a.jpg", "b.jpg", "c.jpg", "d.jpg", "e.jpg"] {
+            fs::write(base.join(name), name)?;
+        }
+
+        run(
+            base.to_str().unwrap(),
+            &RunOptions {
+                subfolders: 2,
+                prefix: "pack".to_string(),
+                rename_template: Some("{folder}_{index}.{ext}".to_string()),
+                ..Default::default()
+            },
+        )?;
+
+        for (name, original) in [
+            ("pack-1/pack-1_1.jpg", "a.jpg"),
+            ("pack-1/pack-1_2.jpg", "b.jpg"),
+            ("pack-1/pack-1_3.jpg", "c.jpg"),
+            ("pack-2/pack-2_1.jpg", "d.jpg"),
+            ("pack-2/pack-2_2.jpg", "e.jpg"),
+        ] {
+            assert_eq!(fs::read_to_string(base.join(name))?, original);
+        }
+        assert_eq!(fs::read_dir(base.join("pack-2"))?.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dry_run_preview_marks_unchanged() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for i in 0..6 {
+            File::create(base.join(format!("file{}.txt", i)))?;
+        }
+        let mut opts = RunOptions {
+            matching: "*.txt".to_string(),
+            subfolders: 3,
+            prefix: "pack".to_string(),
+            ..Default::default()
+        };
+        run(base.to_str().unwrap(), &opts)?;
+
+        // removing file1 from pack-1 pulls one file forward out of each later folder
+        fs::remove_file(base.join("pack-1/file1.txt"))?;
+        opts.dry_run = true;
+        let plan = plan(base.to_str().unwrap(), &opts)?;
+        let preview = render_dry_run_preview(
+            &plan.display_pairs(),
+            &PreviewOptions::default(),
+            &Theme::unicode(),
+        );
+
+        assert!(preview.contains("file0.txt (unchanged)"));
+        assert!(preview.contains("file2.txt <- pack-2"));
+        assert!(preview.contains("file3.txt (unchanged)"));
+        assert!(preview.contains("file4.txt <- pack-3"));
+        assert!(preview.contains("file5.txt (unchanged)"));
+        assert!(preview.contains("  Moves:         2"));
+        assert!(preview.contains("  Unchanged:     3"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn collated_sort_groups_accents() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        for name in ["zebra.txt", "éclair.txt", "eclair.txt", "Eagle.txt"] {
+            File::create(base.join(name))?;
+        }
+        let names = |opts: &RunOptions| -> Result<Vec<String>> {
+            Ok(collect_files(base, opts)?
+                .files
+                .iter()
+                .map(|p| p.path.file_name().unwrap().to_string_lossy().to_string())
+                .collect())
+        };
+
+        let mut opts = RunOptions::default();
+        assert_eq!(
+            names(&opts)?,
+            vec!["Eagle.txt", "eclair.txt", "zebra.txt", "éclair.txt"]
+        );
+
+        opts.collate = true;
+        assert_eq!(
+            names(&opts)?,
+            vec!["Eagle.txt", "eclair.txt", "éclair.txt", "zebra.txt"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn ascii_preview_is_ascii() {
+        let moves: Vec<(String, String)> = (0..5)
+            .map(|i| {
+                (
+                    format!("/base/f{}.txt", i),
+                    format!("/base/pack-{}/f{}.txt", i % 2 + 1, i),
+                )
+            })
+            .collect();
+
+        let preview = render_dry_run_preview(&moves, &PreviewOptions::default(), &Theme::ascii());
+        assert!(preview.is_ascii());
+        assert!(preview.contains("|   |-- f0.txt"));
+        assert!(preview.contains("    `-- f3.txt"));
+
+        assert!(
+            !render_dry_run_preview(&moves, &PreviewOptions::default(), &Theme::unicode())
+                .is_ascii()
+        );
+    }
+
+    #[test]
+    fn redo_reports_counts_by_origin() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        fs::create_dir_all(base.join("pack-1"))?;
+        fs::create_dir_all(base.join("pack-2"))?;
+        for name in ["a", "b", "c"] {
+            File::create(base.join("pack-1").join(format!("{}.txt", name)))?;
+        }
+        for name in ["d", "e", "f"] {
+            File::create(base.join("pack-2").join(format!("{}.txt", name)))?;
+        }
+
+        let report = run(
+            base.to_str().unwrap(),
+            &RunOptions {
+                matching: "*.txt".to_string(),
+                subfolders: 3,
+                prefix: "pack".to_string(),
+                ..Default::default()
+            },
+        )?;
+
+        // a b | c d | e f
+        let pack1 = &report.by_origin["pack-1"];
+        assert_eq!(pack1.get("pack-1"), Some(&2));
+        assert_eq!(pack1.get("pack-2"), Some(&1));
+        let pack2 = &report.by_origin["pack-2"];
+        assert_eq!(pack2.get("pack-2"), Some(&1));
+        assert_eq!(pack2.get("pack-3"), Some(&2));
+        assert_eq!(report.moved, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn by_origin_is_relative_to_the_base_with_output_dir() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path().join("in");
+        fs::create_dir(&base)?;
+        for name in ["a", "b", "c"] {
+            File::create(base.join(format!("{}.txt", name)))?;
+        }
+        let mut opts = RunOptions {
+            subfolders: 2,
+            output_dir: Some(dir.path().join("out")),
+            dry_run: true,
+            capture_preview: true,
+            ..Default::default()
+        };
+
+        // Every entry comes straight out of the base, not out of some folder named `in`
+        let preview = run(base.to_str().unwrap(), &opts)?;
+        assert_eq!(preview.by_origin.keys().collect::<Vec<_>>(), ["."]);
+        assert!(
+            !preview.preview.contains("By origin"),
+            "{}",
+            preview.preview
+        );
+
+        opts.dry_run = false;
+        let report = run(base.to_str().unwrap(), &opts)?;
+        assert_eq!(report.by_origin.keys().collect::<Vec<_>>(), ["."]);
+        assert_eq!(report.by_origin["."].values().sum::<usize>(), 3);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn no_canonicalize_keeps_symlinked_prefix() -> Result<()> {
+        let dir = tempdir()?;
+        let real = dir.path().join("real");
+        let link = dir.path().join("link");
+        fs::create_dir_all(&real)?;
+        std::os::unix::fs::symlink(&real, &link)?;
+        for i in 0..3 {
+            File::create(real.join(format!("f{}.txt", i)))?;
+        }
+
+        let mut opts = RunOptions {
+            subfolders: 2,
+            prefix: "pack".to_string(),
+            ..Default::default()
+        };
+        let canonical = plan(link.to_str().unwrap(), &opts)?;
+        assert!(canonical.moves().all(|m| !m.dest.starts_with(&link)));
+
+        opts.no_canonicalize = true;
+        let logical = plan(link.join("../link").to_str().unwrap(), &opts)?;
+        assert!(
+            logical
+                .moves()
+                .all(|m| m.src.starts_with(&link) && m.dest.starts_with(&link))
+        );
+
+        // the real run moves through the symlink into the real directory
+        run(link.to_str().unwrap(), &opts)?;
+        assert!(real.join("pack-1/f0.txt").is_file());
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_all_combines_bases() -> Result<()> {
+        let first = tempdir()?;
+        let second = tempdir()?;
+        for i in 0..4 {
+            File::create(first.path().join(format!("a{}.txt", i)))?;
+        }
+        for i in 0..2 {
+            File::create(second.path().join(format!("b{}.txt", i)))?;
+        }
+        let missing = first.path().join("does-not-exist");
+
+        let bases = [
+            first.path().to_str().unwrap(),
+            missing.to_str().unwrap(),
+            second.path().to_str().unwrap(),
+        ];
+        let mut opts = RunOptions {
+            matching: "*.txt".to_string(),
+            subfolders: 2,
+            prefix: "pack".to_string(),
+            ..Default::default()
+        };
+        let report = run_all(&bases, &opts)?;
+
+        for base in [first.path(), second.path()] {
+            assert!(base.join("pack-1").is_dir() && base.join("pack-2").is_dir());
+        }
+        assert_eq!(report.folders, 4);
+        assert_eq!(report.moved, 6);
+        assert_eq!(report.base_errors.len(), 1);
+        assert!(report.base_errors[0].contains("does-not-exist"));
+        // Nothing else to run, so the failure is the outcome
+        let alone = run_all(&[missing.to_str().unwrap()], &opts).unwrap_err();
+        assert!(format!("{:#}", alone).contains("does-not-exist"));
+
+        opts.strict = true;
+        assert!(run_all(&bases, &opts).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn redo_prefix_respects_ignore_case() -> Result<()> {
+        let dir = tempdir()?;
+        let base = dir.path();
+        fs::create_dir_all(base.join("Pack-1"))?;
+        File::create(base.join("Pack-1/a.txt"))?;
+        File::create(base.join("b.txt"))?;
+
+        let mut opts = RunOptions {
+            matching: "*.txt".to_string(),
+            prefix: "pack".to_string(),
+            ..Default::default()
+        };
+        let canonical = fs::canonicalize(base)?;
+        let sources = |opts: &RunOptions| -> Result<Vec<PathBuf>> {
+            Ok(plan(base.to_str().unwrap(), opts)?
+                .moves()
+                .map(|m| m.src.strip_prefix(&canonical).unwrap().to_path_buf())
+                .collect())
+        };
+
+        assert_eq!(
+            sources(&opts)?,
+            vec![PathBuf::from("Pack-1/a.txt"), PathBuf::from("b.txt")]
+        );
+
+        opts.ignore_case = false;
+        assert_eq!(sources(&opts)?, vec![PathBuf::from("b.txt")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_strip_prefix_safe() -> Result<()> {
+        // Use "." explicitly to simulate the common cause of StripPrefixError
+        let dir = tempdir()?;
+        let base = dir.path();
 
         // create some files
         for i in 0..3 {
@@ -452,8 +6177,15 @@ mod tests {
         }
 
         // Run collect_files directly to ensure no panic
-        let result =
-            std::panic::catch_unwind(|| collect_files(base, "*.txt", true, "pack").unwrap());
+        let result = std::panic::catch_unwind(|| {
+            let opts = RunOptions {
+                matching: "*.txt".to_string(),
+                recursive: true,
+                prefix: "pack".to_string(),
+                ..Default::default()
+            };
+            collect_files(base, &opts).unwrap()
+        });
 
         assert!(
             result.is_ok(),
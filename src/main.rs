@@ -1,32 +1,217 @@
-use clap::Parser;
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use std::io::{BufRead, IsTerminal, Write};
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 
 /// Move matching files into equally-sized subfolders
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
-struct Args {
-/// Path to the directory to search
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+#[command(subcommand)]
+command: Option<Command>,
+
+
+#[command(flatten)]
+args: Option<Args>,
+}
+
+
+#[derive(Subcommand, Debug)]
+enum Command {
+/// Check that a previous distribution with the same options is intact
+Verify(Box<Args>),
+
+/// Move every file out of the target folders back into the base path
+Flatten(FlattenArgs),
+
+/// Finish an interrupted run from its journal
+Resume(ResumeArgs),
+}
+
+
+#[derive(clap::Args, Debug)]
+struct ResumeArgs {
+/// Base path of the interrupted run
 path: String,
 
 
-/// Glob pattern for matching files (shell-style). Default: "*"
+/// Journal the interrupted run was writing
+#[arg(long, required = true)]
+journal: std::path::PathBuf,
+
+
+/// Print actions without performing them
+#[arg(long)]
+dry_run: bool,
+}
+
+
+#[derive(clap::Args, Debug)]
+struct FlattenArgs {
+/// Path(s) to the directories holding the target folders
+#[arg(required = true)]
+paths: Vec<String>,
+
+
+/// Glob pattern for the files to gather. Default: "*"
 #[arg(short, long, default_value = "*")]
 matching: String,
 
 
-/// Number of subfolders to split into
+/// Prefix of the target folders to empty. Default: "group"
+#[arg(short, long, env = "REFOLDER_PREFIX", default_value = "group")]
+prefix: String,
+
+
+/// When a gathered file collides with one in the base path: error | rename | skip
+#[arg(long, default_value = "error")]
+on_collision: refolder::OnCollision,
+
+
+/// Remove the target folders once they are empty
+#[arg(long)]
+remove_empty: bool,
+
+
+/// Print actions without performing them
+#[arg(long)]
+dry_run: bool,
+}
+
+
+impl FlattenArgs {
+/// Turn the arguments into library options.
+fn into_options(self) -> (Vec<String>, refolder::RunOptions, bool) {
+let opts = refolder::RunOptions {
+matching: self.matching,
+prefix: self.prefix,
+on_collision: self.on_collision,
+dry_run: self.dry_run,
+..Default::default()
+};
+(self.paths, opts, self.remove_empty)
+}
+}
+
+
+#[derive(clap::Args, Debug)]
+struct Args {
+/// Path(s) to the directories to search; each is distributed independently
+#[arg(required = true)]
+paths: Vec<String>,
+
+
+/// Glob pattern(s) for matching files (shell-style); `!glob` drops what it matches. Default: "*"
+#[arg(short, long, default_value = "*", num_args = 1..)]
+matching: Vec<String>,
+
+
+/// How --matching is read: glob | regex (searched in the file name) | literal (exact file name)
+#[arg(long, default_value = "glob")]
+match_type: refolder::MatchType,
+
+
+/// Number of subfolders to split into, or the share of the files each holds, e.g. 25% (required unless grouping with --by, --max-per-folder or --one-per-folder)
+#[arg(short, long)]
+subfolders: Option<Subfolders>,
+
+
+/// Create the target folders in this directory instead of inside each base path
 #[arg(short, long)]
-subfolders: usize,
+output_dir: Option<std::path::PathBuf>,
+
+
+/// Nest all target folders under a parent named from the current UTC time (%Y %m %d %H %M %S)
+#[arg(long, value_name = "FORMAT", num_args = 0..=1, require_equals = true, default_missing_value = "%Y-%m-%dT%H-%M")]
+timestamp_folder: Option<String>,
+
+
+/// Put every matched entry into its own folder
+#[arg(long, conflicts_with = "subfolders")]
+one_per_folder: bool,
+
+
+/// Use as many folders as needed to hold at most N entries each
+#[arg(long, value_name = "N", conflicts_with_all = ["subfolders", "one_per_folder"])]
+max_per_folder: Option<usize>,
+
+
+/// Create no folder when only one would result; entries stay in (or return to) the base path
+#[arg(long)]
+collapse_single: bool,
+
+
+/// After the run, remove empty numbered folders left over from an earlier run with more folders
+#[arg(long)]
+replace_existing_folders: bool,
+
+
+/// Refuse to create more than N folders
+#[arg(long, value_name = "N")]
+max_folders: Option<usize>,
+
+
+/// Refuse to run if fewer than N entries match (guards against a wrong path)
+#[arg(long, value_name = "N")]
+min_files: Option<usize>,
 
 
 /// Prefix for created subfolders. Default: "group"
-#[arg(short, long, default_value = "group")]
+#[arg(short, long, env = "REFOLDER_PREFIX", default_value = "group")]
 prefix: String,
 
 
-/// Suffix style: numbers | letters | none
-#[arg(long, default_value = "numbers")]
-suffix: String,
+/// Derive the prefix from the matched files' common name prefix (e.g. IMG_0001.jpg -> IMG)
+#[arg(long, conflicts_with = "prefix")]
+auto_prefix: bool,
+
+
+/// Suffix style: numbers | letters | hex | roman | words | none
+#[arg(long, env = "REFOLDER_SUFFIX", default_value = "numbers")]
+suffix: refolder::SuffixStyle,
+
+
+/// Text between the prefix and the suffix
+#[arg(long, default_value = "-")]
+separator: String,
+
+
+/// Number given to the first folder
+#[arg(long, default_value_t = 1)]
+start_index: usize,
+
+
+/// Folder name template, e.g. "{prefix}-{index}-[{count}files]"
+#[arg(long)]
+template: Option<String>,
+
+
+/// Explicit folder names used in order instead of generated ones, e.g. --labels Q1,Q2,Q3,Q4
+#[arg(long, value_delimiter = ',', conflicts_with = "template")]
+labels: Vec<String>,
+
+
+/// Reuse --labels with a round number (Q1-2, Q2-2, ...) when there are more folders than labels
+#[arg(long, requires = "labels")]
+cycle_labels: bool,
+
+
+/// Fill these existing folders instead of creating numbered ones, e.g. --into inbox,archive,review
+#[arg(long, value_delimiter = ',', conflicts_with_all = ["labels", "template"])]
+into: Vec<String>,
+
+
+/// Create folders listed in --into that do not exist yet
+#[arg(long, requires = "into")]
+create_missing: bool,
+
+
+/// Zero-pad numeric suffixes to N digits (0: no padding), or "auto" to fit the folder count
+#[arg(long, default_value = "0")]
+pad: refolder::Pad,
 
 
 /// Recurse into subdirectories
@@ -39,25 +224,823 @@ recursive: bool,
 dry_run: bool,
 
 
+/// Move nothing; fail if running now would still move any entry
+#[arg(long)]
+check_stable: bool,
+
+
+/// Print the number of matching entries and exit, without planning or moving anything
+#[arg(long, conflicts_with = "check_stable")]
+count_only: bool,
+
+
+/// With --dry-run, exit non-zero if any entry would actually move
+#[arg(long, requires = "dry_run")]
+fail_on_change: bool,
+
+
+/// Ask for confirmation before a run that would move N or more entries
+#[arg(long, value_name = "N")]
+confirm_threshold: Option<usize>,
+
+
+/// Answer yes to the --confirm-threshold prompt
+#[arg(short, long)]
+yes: bool,
+
+
 /// Overwrite existing files/folders in destination
 #[arg(short, long)]
 force: bool,
+
+
+/// Entries to distribute: files | dirs | both
+#[arg(long, default_value = "files")]
+entries: refolder::Entries,
+
+
+/// Fail on any error while walking the directory instead of skipping the entry
+#[arg(long)]
+strict: bool,
+
+
+/// Overwrite an existing destination only if the source is newer; otherwise leave it
+#[arg(short, long, conflicts_with = "force")]
+update: bool,
+
+
+/// Leave files that are open or locked by another process in place instead of aborting
+#[arg(long)]
+skip_locked: bool,
+
+
+/// Refuse to run if any destination would land outside the base path (or --output-dir)
+#[arg(long)]
+strict_paths: bool,
+
+
+/// Skip unreadable entries without a warning each; only their count is reported
+#[arg(long, conflicts_with = "strict")]
+quiet_errors: bool,
+
+
+/// When nothing matches: ok (print a note) | error (fail the run)
+#[arg(long, value_name = "POLICY", default_value = "ok")]
+no_op_on_empty_match: refolder::EmptyMatch,
+
+
+/// Grouping: count | hash | extension | parent
+#[arg(long, default_value = "count")]
+by: refolder::GroupBy,
+
+
+/// Folder names for --by hash|extension|parent and --group-map: key | index (default: <prefix>-<key>)
+#[arg(long)]
+folder_name_from: Option<refolder::FolderNameFrom>,
+
+
+/// Walk the directory tree with N threads (default: single-threaded)
+#[arg(long)]
+threads: Option<usize>,
+
+
+/// How a real run moves entries: sequential | parallel (on --threads worker threads, once the plan is complete)
+#[arg(long, value_name = "MODE", default_value = "sequential")]
+move_mode: refolder::MoveMode,
+
+
+/// Only match these extensions, e.g. --ext jpg,png (combines with --matching)
+#[arg(long, value_delimiter = ',')]
+ext: Vec<String>,
+
+
+/// Skip entries whose name matches this glob (repeatable), also inside existing target folders
+#[arg(long)]
+exclude: Vec<String>,
+
+
+/// Do not descend into directories whose name matches this glob (repeatable), e.g. node_modules
+#[arg(long, value_name = "GLOB")]
+prune_dir: Vec<String>,
+
+
+/// Never walk, move or redo a directory with exactly this name (repeatable), e.g. _originals
+#[arg(long, value_name = "NAME")]
+exclude_dir_name: Vec<String>,
+
+
+/// Skip entries whose name starts with a dot
+#[arg(long)]
+skip_hidden: bool,
+
+
+/// Manifest CSV: written after a real run, read back by `verify`
+#[arg(long)]
+manifest: Option<std::path::PathBuf>,
+
+
+/// Add a sha256 column to the manifest, hashed from each file at its destination
+#[arg(long, requires = "manifest")]
+checksum_manifest: bool,
+
+
+/// Record each run (its plan, then each move as it is made) in this journal file
+#[arg(long)]
+journal: Option<std::path::PathBuf>,
+
+
+/// Only distribute entries modified since the last run in --journal
+#[arg(long, requires = "journal")]
+since_run: bool,
+
+
+/// Only distribute files whose name is not already somewhere under this reference directory
+#[arg(long, value_name = "REF_DIR")]
+mirror: Option<std::path::PathBuf>,
+
+
+/// Pin files to folders with "<file name> -> <folder>" lines; unlisted files are distributed as usual
+#[arg(long, value_name = "FILE")]
+mapping: Option<std::path::PathBuf>,
+
+
+/// Group files by the key a CSV ("name,key" rows) or JSON ({"name": "key"}) file gives their name, one folder per key
+#[arg(long, value_name = "FILE", conflicts_with_all = ["by", "into"])]
+group_map: Option<std::path::PathBuf>,
+
+
+/// Group key for files missing from --group-map (default: leave them in place)
+#[arg(long, value_name = "KEY", requires = "group_map")]
+group_map_default: Option<String>,
+
+
+/// Normalize destination file names: lower | kebab | none
+#[arg(long, default_value = "none")]
+rename_case: refolder::RenameCase,
+
+
+/// Destination file name template: {stem}, {ext}, {index} (position in the folder), {folder}
+#[arg(long, value_name = "TEMPLATE")]
+rename_template: Option<String>,
+
+
+/// Unicode-normalize destination file names: nfc | nfd
+#[arg(long)]
+normalize_unicode: Option<refolder::UnicodeForm>,
+
+
+/// When destinations collide: error | rename | skip
+#[arg(long, default_value = "error")]
+on_collision: refolder::OnCollision,
+
+
+/// Keep destination file names unique across all folders, adding -N counters as needed
+#[arg(long)]
+unique_names: bool,
+
+
+/// Bucket assignment: count | round-robin | size
+#[arg(long, default_value = "count")]
+strategy: refolder::Strategy,
+
+
+/// Leave a file in place when its target folder already holds a file with the same content
+#[arg(long)]
+skip_identical_content: bool,
+
+
+/// Move files the pattern does not match (but the other filters allow) into a folder of this name
+#[arg(long, value_name = "NAME")]
+group_unmatched: Option<String>,
+
+
+/// Byte ceiling per folder; files that would go past it spill into extra folders
+#[arg(long, value_name = "BYTES")]
+max_bytes: Option<u64>,
+
+
+/// Smallest a folder may be, in entries (3) or bytes (4096B); smaller folders are merged into the next one
+#[arg(long, value_name = "SIZE", conflicts_with = "max_bytes")]
+min_bucket_size: Option<refolder::MinBucket>,
+
+
+/// Which folders of a count split get the extra files: first | last
+#[arg(long, default_value = "first")]
+remainder: refolder::Remainder,
+
+
+/// Order --strategy size places files in: largest-first | smallest-first
+#[arg(long, default_value = "largest-first")]
+fill: refolder::Fill,
+
+
+/// Do nothing if the existing folders are within PERCENT of an even split
+#[arg(long, value_name = "PERCENT")]
+move_threshold: Option<f64>,
+
+
+/// On a redo, give each new folder the name its files mostly came from, so fewer files move
+#[arg(long)]
+prefer_origin: bool,
+
+
+/// Sort names with Unicode collation (é next to e) instead of byte order
+#[arg(long)]
+collate: bool,
+
+
+/// Sort by the first capture group of this regex in each file name (numbers numerically; unmatched last)
+#[arg(long)]
+sort_key: Option<String>,
+
+
+/// Ignore this leading text in file names when sorting (destinations keep full names)
+#[arg(long, value_name = "STR")]
+strip_prefix: Option<String>,
+
+
+/// Draw the dry-run tree with ASCII characters only
+#[arg(long)]
+ascii: bool,
+
+
+/// Print the plan as JSON to stderr, then carry it out (or preview it with --dry-run)
+#[arg(long)]
+print_plan: bool,
+
+
+/// Annotate the dry-run preview with why each entry went to its folder
+#[arg(long)]
+explain: bool,
+
+
+/// Refuse to run if any entry would move to another filesystem (a copy and delete instead of a rename)
+#[arg(long)]
+same_filesystem_only: bool,
+
+
+/// Fill each new folder under a hidden .<name>.tmp directory and rename it into place once complete
+#[arg(long)]
+stage: bool,
+
+
+/// Fail before moving anything if a target filesystem lacks room for the files copied onto it
+#[arg(long)]
+check_space: bool,
+
+
+/// Leave existing target folders alone and number new ones after the highest existing suffix
+#[arg(long)]
+continue_numbering: bool,
+
+
+/// Output for moves: text (dry-run tree) | jsonl (one JSON object per move, streamed) | table (dry-run columns)
+#[arg(long, default_value = "text")]
+format: refolder::OutputFormat,
+
+
+/// After a real run, print its outcome (counts, bytes, created folders, errors) to stdout: json
+#[arg(long, value_name = "FORMAT")]
+report: Option<refolder::ReportFormat>,
+
+
+/// Order of the folders in the dry-run preview: name | count | size (heaviest first)
+#[arg(long, value_name = "ORDER", default_value = "name")]
+sort_folders_by: refolder::FolderOrder,
+
+
+/// Order of the entries within each folder in the plan, manifest and preview: name | size (largest first) | mtime (oldest first)
+#[arg(long, value_name = "ORDER")]
+sort_within_folder: Option<refolder::EntryOrder>,
+
+
+/// Show at most N entries per folder in the dry-run preview
+#[arg(long, value_name = "N")]
+preview_limit: Option<usize>,
+
+
+/// Show a dry-run preview taller than the terminal through $PAGER
+#[arg(long)]
+paginate: bool,
+
+
+/// Show paths relative to the base path in the preview, --print-plan JSON, manifest and journal
+#[arg(long)]
+relative_output: bool,
+
+
+/// Keep the base path as given (made absolute) instead of resolving symlinks
+#[arg(long)]
+no_canonicalize: bool,
+
+
+/// Case-insensitive glob and target-folder matching: on | off
+#[arg(long, default_value = "on", action = clap::ArgAction::Set, value_parser = clap::builder::BoolishValueParser::new(), hide_possible_values = true)]
+ignore_case: bool,
+}
+
+
+/// A --subfolders value: a folder count, or a percentage of the matched entries per folder.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Subfolders {
+Count(usize),
+Percent(f64),
 }
 
 
-fn main() -> anyhow::Result<()> {
-let args = Args::parse();
-if args.subfolders == 0 {
+impl std::str::FromStr for Subfolders {
+type Err = String;
+
+fn from_str(s: &str) -> Result<Self, Self::Err> {
+match s.strip_suffix('%') {
+Some(pct) => match pct.trim().parse::<f64>() {
+Ok(p) if p > 0.0 && p <= 100.0 => Ok(Subfolders::Percent(p)),
+_ => Err(format!("Invalid percentage '{}'. Use a number above 0 and up to 100, e.g. 25%", s)),
+},
+None => s.parse().map(Subfolders::Count).map_err(|e| format!("{}", e)),
+}
+}
+}
+
+
+impl Args {
+/// Validate the arguments and turn them into library options.
+fn into_options(self) -> anyhow::Result<(Vec<String>, refolder::RunOptions)> {
+let (subfolders, folder_share) = match (self.subfolders, self.by) {
+(Some(Subfolders::Count(n)), _) => (n, None),
+(Some(Subfolders::Percent(p)), _) => (1, Some(p)),
+(None, refolder::GroupBy::Count) if !self.one_per_folder && self.max_per_folder.is_none() && self.group_map.is_none() && !self.count_only => anyhow::bail!("--subfolders is required unless grouping with --by, --group-map, --max-per-folder or --one-per-folder"),
+(None, _) => (1, None),
+};
+if subfolders == 0 {
 anyhow::bail!("--subfolders must be greater than zero");
 }
-refolder::run(
-&args.path,
-&args.matching,
-args.subfolders,
-&args.prefix,
-&args.suffix,
-args.recursive,
-args.dry_run,
-args.force,
-)
-}
\ No newline at end of file
+Ok((
+self.paths,
+refolder::RunOptions {
+matching: self.matching[0].clone(),
+extra_patterns: self.matching[1..].to_vec(),
+match_type: self.match_type,
+subfolders,
+folder_share,
+output_dir: self.output_dir,
+timestamp_folder: self.timestamp_folder,
+max_per_folder: self.max_per_folder,
+max_folders: self.max_folders,
+min_files: self.min_files,
+one_per_folder: self.one_per_folder,
+collapse_single: self.collapse_single,
+replace_existing_folders: self.replace_existing_folders,
+prefix: self.prefix,
+auto_prefix: self.auto_prefix,
+suffix: self.suffix,
+separator: self.separator,
+start_index: self.start_index,
+template: self.template,
+labels: self.labels,
+cycle_labels: self.cycle_labels,
+into: self.into,
+create_missing: self.create_missing,
+pad: self.pad,
+recursive: self.recursive,
+dry_run: self.dry_run,
+force: self.force,
+entries: self.entries,
+strict: self.strict,
+update: self.update,
+skip_locked: self.skip_locked,
+strict_paths: self.strict_paths,
+quiet_errors: self.quiet_errors,
+on_empty_match: self.no_op_on_empty_match,
+by: self.by,
+folder_name_from: self.folder_name_from,
+threads: self.threads,
+move_mode: self.move_mode,
+ext: self.ext,
+exclude: self.exclude,
+prune_dir: self.prune_dir,
+exclude_dir_name: self.exclude_dir_name,
+skip_hidden: self.skip_hidden,
+manifest: self.manifest,
+checksum_manifest: self.checksum_manifest,
+journal: self.journal,
+since_run: self.since_run,
+mirror: self.mirror,
+mapping: self.mapping,
+group_map: self.group_map,
+group_map_default: self.group_map_default,
+rename_case: self.rename_case,
+rename_template: self.rename_template,
+normalize_unicode: self.normalize_unicode,
+on_collision: self.on_collision,
+unique_names: self.unique_names,
+strategy: self.strategy,
+remainder: self.remainder,
+fill: self.fill,
+prefer_origin: self.prefer_origin,
+max_bytes: self.max_bytes,
+min_bucket_size: self.min_bucket_size,
+group_unmatched: self.group_unmatched,
+skip_identical_content: self.skip_identical_content,
+move_threshold: self.move_threshold,
+collate: self.collate,
+sort_key: self.sort_key,
+strip_prefix: self.strip_prefix,
+ascii: self.ascii,
+print_plan: self.print_plan,
+explain: self.explain,
+format: self.format,
+sort_folders_by: self.sort_folders_by,
+sort_within_folder: self.sort_within_folder,
+preview_limit: self.preview_limit,
+capture_preview: self.paginate,
+continue_numbering: self.continue_numbering,
+check_space: self.check_space,
+stage: self.stage,
+same_filesystem_only: self.same_filesystem_only,
+relative_output: self.relative_output,
+no_canonicalize: self.no_canonicalize,
+ignore_case: self.ignore_case,
+cancel: None,
+},
+))
+}
+}
+
+
+/// Every planned entry was moved (or there was nothing to check).
+const EXIT_OK: u8 = 0;
+/// The run failed.
+const EXIT_ERROR: u8 = 1;
+/// No entry matched.
+const EXIT_NO_MATCH: u8 = 2;
+/// The run finished but left some entries behind.
+const EXIT_PARTIAL: u8 = 3;
+
+
+/// The flag set by Ctrl-C. The handler can only be installed once per process.
+fn cancel_flag() -> anyhow::Result<Arc<AtomicBool>> {
+static CANCEL: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+if let Some(cancel) = CANCEL.get() {
+return Ok(cancel.clone());
+}
+let cancel = Arc::new(AtomicBool::new(false));
+let flag = cancel.clone();
+ctrlc::set_handler(move || flag.store(true, Ordering::Relaxed))
+.context("Failed to install the Ctrl-C handler")?;
+Ok(CANCEL.get_or_init(|| cancel).clone())
+}
+
+
+/// Console logger for the library's messages: info on stdout, warnings and errors on
+/// stderr. Debug records (individual moves) are not shown.
+struct ConsoleLogger;
+
+
+impl log::Log for ConsoleLogger {
+fn enabled(&self, metadata: &log::Metadata) -> bool {
+metadata.level() <= log::Level::Info
+}
+
+fn log(&self, record: &log::Record) {
+if !self.enabled(record.metadata()) {
+return;
+}
+match record.level() {
+log::Level::Error => eprintln!("Error: {}", record.args()),
+log::Level::Warn => eprintln!("⚠️ Warning: {}", record.args()),
+_ => println!("{}", record.args()),
+}
+}
+
+fn flush(&self) {}
+}
+
+
+/// Ask on `out` whether to go ahead with `pending` moves, reading the answer from `input`.
+/// Below `threshold` nothing is asked and the run goes ahead.
+fn confirm(pending: usize, threshold: usize, input: &mut impl BufRead, out: &mut impl Write) -> std::io::Result<bool> {
+if pending < threshold {
+return Ok(true);
+}
+write!(out, "{} entries will move. Continue? [y/N] ", pending)?;
+out.flush()?;
+let mut answer = String::new();
+input.read_line(&mut answer)?;
+Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+
+/// Print the total number of matching entries across `paths` as a bare integer.
+fn write_count(paths: &[String], opts: &refolder::RunOptions, out: &mut impl Write) -> anyhow::Result<()> {
+let mut total = 0;
+for path in paths {
+total += refolder::count_matches(path, opts)?;
+}
+writeln!(out, "{}", total)?;
+Ok(())
+}
+
+
+/// Write `text` to `out`, or through `pager` when one is given and `text` has more than
+/// `height` lines. If the pager cannot be started the text goes to `out` after all.
+fn write_paged(text: &str, pager: Option<&str>, height: usize, out: &mut impl Write) -> anyhow::Result<()> {
+if let Some(pager) = pager.filter(|_| text.lines().count() > height) {
+let mut words = pager.split_whitespace();
+if let Some(program) = words.next()
+&& let Ok(mut child) = std::process::Command::new(program).args(words).stdin(std::process::Stdio::piped()).spawn()
+{
+if let Some(mut stdin) = child.stdin.take() {
+// Quitting the pager early closes the pipe; that is not an error
+match stdin.write_all(text.as_bytes()) {
+Err(err) if err.kind() != std::io::ErrorKind::BrokenPipe => return Err(err.into()),
+_ => {}
+}
+}
+child.wait().context("Failed waiting for the pager")?;
+return Ok(());
+}
+}
+out.write_all(text.as_bytes())?;
+Ok(())
+}
+
+
+/// How many lines fit on the screen: `$LINES` if set, else the rows of the terminal on
+/// stdout, else 24.
+fn page_height() -> usize {
+std::env::var("LINES").ok().and_then(|lines| lines.parse().ok()).or_else(terminal_rows).unwrap_or(24)
+}
+
+
+/// Rows of the terminal on stdout, if stdout is one.
+#[cfg(unix)]
+fn terminal_rows() -> Option<usize> {
+let mut size = libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+// SAFETY: TIOCGWINSZ only writes a `winsize` through the pointer, which outlives the call
+let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+(ok && size.ws_row > 0).then_some(usize::from(size.ws_row))
+}
+
+
+#[cfg(not(unix))]
+fn terminal_rows() -> Option<usize> {
+None
+}
+
+
+/// Map the outcome of `try_main` to the process exit code.
+fn exit_code(outcome: &anyhow::Result<Option<refolder::RunReport>>) -> u8 {
+match outcome {
+Err(_) => EXIT_ERROR,
+Ok(Some(report)) if report.matched == 0 && report.moved == 0 => EXIT_NO_MATCH,
+Ok(Some(report)) if report.is_partial() => EXIT_PARTIAL,
+Ok(_) => EXIT_OK,
+}
+}
+
+
+fn main() -> std::process::ExitCode {
+static LOGGER: ConsoleLogger = ConsoleLogger;
+if log::set_logger(&LOGGER).is_ok() {
+log::set_max_level(log::LevelFilter::Info);
+}
+let outcome = try_main(Cli::parse());
+if let Err(err) = &outcome {
+eprintln!("Error: {:?}", err);
+}
+std::process::ExitCode::from(exit_code(&outcome))
+}
+
+
+/// Run the command line; the report is returned for runs, `None` for checks.
+fn try_main(cli: Cli) -> anyhow::Result<Option<refolder::RunReport>> {
+match cli.command {
+Some(Command::Flatten(args)) => {
+let (paths, mut opts, remove_empty) = args.into_options();
+opts.cancel = Some(cancel_flag()?);
+let mut report = refolder::RunReport::default();
+for path in &paths {
+report.merge(refolder::flatten(path, &opts, remove_empty)?);
+}
+return Ok(Some(report));
+}
+Some(Command::Resume(args)) => {
+let opts = refolder::RunOptions {
+journal: Some(args.journal),
+dry_run: args.dry_run,
+cancel: Some(cancel_flag()?),
+..Default::default()
+};
+return Ok(Some(refolder::resume(&args.path, &opts)?));
+}
+Some(Command::Verify(args)) => {
+let (paths, opts) = args.into_options()?;
+let mut discrepancies = Vec::new();
+for path in &paths {
+discrepancies.extend(refolder::verify(path, &opts)?.discrepancies);
+}
+for d in &discrepancies {
+eprintln!("{}", d);
+}
+if !discrepancies.is_empty() {
+anyhow::bail!("Verification failed: {} discrepancies", discrepancies.len());
+}
+println!("Distribution is intact.");
+}
+None => {
+// Without a subcommand clap requires PATHS, so the flattened arguments are present.
+let args = cli.args.expect("PATHS are required without a subcommand");
+let check_stable = args.check_stable;
+let count_only = args.count_only;
+let fail_on_change = args.fail_on_change;
+let json_report = args.report == Some(refolder::ReportFormat::Json);
+let confirm_threshold = args.confirm_threshold.filter(|_| !args.yes);
+let (paths, opts) = args.into_options()?;
+if count_only {
+write_count(&paths, &opts, &mut std::io::stdout().lock())?;
+return Ok(None);
+}
+if check_stable {
+let mut pending = Vec::new();
+for path in &paths {
+pending.extend(refolder::check_stable(path, &opts)?);
+}
+for m in &pending {
+eprintln!("would move: {} -> {}", m.src.display(), m.dest.display());
+}
+if !pending.is_empty() {
+anyhow::bail!("Plan is not stable: {} entries would move", pending.len());
+}
+println!("Plan is stable.");
+return Ok(None);
+}
+if let Some(threshold) = confirm_threshold
+&& !opts.dry_run
+{
+// Bases that fail to plan are reported by the run itself
+let pending: usize = paths
+.iter()
+.map(|path| refolder::check_stable(path, &opts).map_or(0, |moves| moves.len()))
+.sum();
+if !confirm(pending, threshold, &mut std::io::stdin().lock(), &mut std::io::stderr())? {
+anyhow::bail!("Aborted: {} entries left in place", pending);
+}
+}
+// Ctrl-C stops the run between moves instead of killing it mid-copy
+let mut opts = opts;
+opts.cancel = Some(cancel_flag()?);
+let report = refolder::run_all(&paths, &opts)?;
+if opts.capture_preview {
+let stdout = std::io::stdout();
+let pager = std::env::var("PAGER").ok().filter(|_| stdout.is_terminal());
+write_paged(&report.preview, pager.as_deref(), page_height(), &mut stdout.lock())?;
+}
+if fail_on_change && report.moved > 0 {
+anyhow::bail!("{} entries would move", report.moved);
+}
+// A failed base is the outcome; a summary of the others would read as success
+for err in &report.base_errors {
+eprintln!("Error: {}", err);
+}
+if !report.base_errors.is_empty() {
+anyhow::bail!("{} of {} base paths failed", report.base_errors.len(), paths.len());
+}
+// Keep stdout to one JSON object per line
+if !opts.dry_run && json_report {
+println!("{}", report.to_json());
+} else if !opts.dry_run && opts.format == refolder::OutputFormat::Text && (report.matched > 0 || report.moved > 0) {
+// Only after a run that had something to move; "Nothing to do" needs no total
+println!("Total bytes moved: {}", refolder::format_bytes(report.bytes_moved));
+}
+if opts.quiet_errors && !report.walk_errors.is_empty() {
+println!("Skipped {} unreadable entries", report.walk_errors.len());
+}
+return Ok(Some(report));
+}
+}
+Ok(None)
+}
+
+
+#[cfg(test)]
+mod tests {
+use super::*;
+
+#[test]
+fn prefix_and_suffix_fall_back_to_env() {
+// Inspected rather than set: other tests parse `Cli` in parallel and would read the variables
+use clap::CommandFactory;
+let cli = Cli::command();
+let env_of = |id: &str| {
+cli.get_arguments()
+.find(|arg| arg.get_id() == id)
+.and_then(|arg| arg.get_env())
+.map(|env| env.to_string_lossy().to_string())
+};
+assert_eq!(env_of("prefix").as_deref(), Some("REFOLDER_PREFIX"));
+assert_eq!(env_of("suffix").as_deref(), Some("REFOLDER_SUFFIX"));
+
+// A flag still wins over the environment
+let from_cli = Cli::try_parse_from(["refolder", "dir", "-s", "2", "--prefix", "cli"]).unwrap();
+assert_eq!(from_cli.args.unwrap().prefix, "cli");
+}
+
+#[test]
+fn confirm_prompts_only_at_threshold() {
+let mut out = Vec::new();
+assert!(confirm(4, 5, &mut "n\n".as_bytes(), &mut out).unwrap());
+assert!(out.is_empty());
+
+assert!(confirm(5, 5, &mut "y\n".as_bytes(), &mut out).unwrap());
+assert_eq!(String::from_utf8(out).unwrap(), "5 entries will move. Continue? [y/N] ");
+
+let mut out = Vec::new();
+assert!(!confirm(9, 5, &mut "\n".as_bytes(), &mut out).unwrap());
+assert!(!out.is_empty());
+}
+
+
+#[test]
+fn subfolders_accepts_a_percentage() {
+let dir = tempfile::tempdir().unwrap();
+let base = dir.path().to_str().unwrap();
+for i in 0..20 {
+std::fs::File::create(dir.path().join(format!("f{:02}.txt", i))).unwrap();
+}
+assert!(Cli::try_parse_from(["refolder", base, "-s", "0%"]).is_err());
+assert!(Cli::try_parse_from(["refolder", base, "-s", "150%"]).is_err());
+
+try_main(Cli::try_parse_from(["refolder", base, "-s", "25%"]).unwrap()).unwrap();
+for i in 1..=4 {
+assert_eq!(std::fs::read_dir(dir.path().join(format!("group-{}", i))).unwrap().count(), 5);
+}
+assert!(!dir.path().join("group-5").exists());
+}
+
+
+#[test]
+fn count_only_prints_the_bare_count() {
+let dir = tempfile::tempdir().unwrap();
+let base = dir.path().to_str().unwrap();
+for name in ["a.txt", "b.txt", "c.txt", "d.log"] {
+std::fs::File::create(dir.path().join(name)).unwrap();
+}
+let cli = Cli::try_parse_from(["refolder", base, "--matching", "*.txt", "--count-only"]).unwrap();
+let (paths, opts) = cli.args.unwrap().into_options().unwrap();
+
+let mut out = Vec::new();
+write_count(&paths, &opts, &mut out).unwrap();
+assert_eq!(String::from_utf8(out).unwrap(), "3\n");
+assert!(dir.path().join("a.txt").is_file());
+}
+
+
+#[test]
+fn paginate_without_a_pager_prints_the_whole_preview() {
+let dir = tempfile::tempdir().unwrap();
+let base = dir.path().to_str().unwrap();
+for i in 0..30 {
+std::fs::File::create(dir.path().join(format!("f{:02}.txt", i))).unwrap();
+}
+let cli = Cli::try_parse_from(["refolder", base, "-s", "3", "--dry-run", "--paginate"]).unwrap();
+let (paths, opts) = cli.args.unwrap().into_options().unwrap();
+let report = refolder::run_all(&paths, &opts).unwrap();
+assert!(report.preview.contains("f29.txt"));
+
+let mut out = Vec::new();
+write_paged(&report.preview, None, 5, &mut out).unwrap();
+assert_eq!(String::from_utf8(out).unwrap(), report.preview);
+assert!(dir.path().join("f00.txt").is_file());
+}
+
+
+#[test]
+fn outcomes_map_to_exit_codes() {
+let full = refolder::RunReport { matched: 4, moved: 4, ..Default::default() };
+let partial = refolder::RunReport { matched: 4, moved: 3, skipped: 1, ..Default::default() };
+let none = refolder::RunReport::default();
+
+assert_eq!(exit_code(&Ok(Some(full))), EXIT_OK);
+assert_eq!(exit_code(&Ok(Some(partial))), EXIT_PARTIAL);
+assert_eq!(exit_code(&Ok(Some(none))), EXIT_NO_MATCH);
+assert_eq!(exit_code(&Err(anyhow::anyhow!("boom"))), EXIT_ERROR);
+assert_eq!(exit_code(&Ok(None)), EXIT_OK);
+}
+
+#[test]
+fn fail_on_change_rejects_unorganized_tree() {
+let dir = tempfile::tempdir().unwrap();
+let base = dir.path().to_str().unwrap();
+for name in ["a.txt", "b.txt"] {
+std::fs::File::create(dir.path().join(name)).unwrap();
+}
+let check = || try_main(Cli::try_parse_from(["refolder", base, "-s", "2", "--dry-run", "--fail-on-change"]).unwrap());
+
+let err = check().unwrap_err();
+assert!(err.to_string().contains("2 entries would move"), "{}", err);
+
+try_main(Cli::try_parse_from(["refolder", base, "-s", "2"]).unwrap()).unwrap();
+assert_eq!(check().unwrap().unwrap().moved, 0);
+}
+}
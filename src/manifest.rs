@@ -0,0 +1,135 @@
+//! CSV manifest recording where every entry of a run ended up.
+//!
+//! The file has a `source,destination` header followed by one row per entry, in plan order.
+//! A checksummed manifest adds a `sha256` column. Fields are quoted when they contain a
+//! comma, quote or newline.
+
+use crate::{Plan, PlannedMove};
+use anyhow::{Context, Result, anyhow};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const HEADER: [&str; 2] = ["source", "destination"];
+const CHECKSUM_HEADER: [&str; 3] = ["source", "destination", "sha256"];
+
+/// Write `plan` to `path` as a CSV manifest.
+pub fn write(path: &Path, plan: &Plan) -> Result<()> {
+    let mut out = String::new();
+    push_row(&mut out, &HEADER);
+    for m in plan.moves() {
+        push_row(
+            &mut out,
+            &[&m.src.to_string_lossy(), &m.dest.to_string_lossy()],
+        );
+    }
+    fs::write(path, out).with_context(|| format!("Failed writing manifest {}", path.display()))
+}
+
+/// Write `plan` to `path` as a CSV manifest with a SHA-256 column: `digests` holds one
+/// lowercase hex digest per move, in plan order, empty for entries that are not files or
+/// were left in place.
+pub fn write_checksummed(path: &Path, plan: &Plan, digests: &[String]) -> Result<()> {
+    let mut out = String::new();
+    push_row(&mut out, &CHECKSUM_HEADER);
+    for (m, digest) in plan.moves().zip(digests) {
+        push_row(
+            &mut out,
+            &[&m.src.to_string_lossy(), &m.dest.to_string_lossy(), digest],
+        );
+    }
+    fs::write(path, out).with_context(|| format!("Failed writing manifest {}", path.display()))
+}
+
+/// Read back the moves recorded in the manifest at `path`.
+pub fn read(path: &Path) -> Result<Vec<PlannedMove>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed reading manifest {}", path.display()))?;
+    let mut rows = parse_rows(&content).into_iter();
+
+    let columns = match rows.next() {
+        Some(header) if header == HEADER => HEADER.len(),
+        Some(header) if header == CHECKSUM_HEADER => CHECKSUM_HEADER.len(),
+        _ => return Err(anyhow!("{} is not a refolder manifest", path.display())),
+    };
+
+    rows.enumerate()
+        .map(|(i, row)| match row.as_slice() {
+            [src, dest, ..] if row.len() == columns => {
+                Ok(PlannedMove::new(PathBuf::from(src), PathBuf::from(dest)))
+            }
+            _ => Err(anyhow!(
+                "Malformed manifest row {} in {}",
+                i + 2,
+                path.display()
+            )),
+        })
+        .collect()
+}
+
+pub(crate) fn push_row(out: &mut String, fields: &[&str]) {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            out.push('"');
+            out.push_str(&field.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(field);
+        }
+    }
+    out.push('\n');
+}
+
+pub(crate) fn parse_rows(content: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => row.push(std::mem::take(&mut field)),
+            '\n' if !in_quotes => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            '\r' if !in_quotes => {}
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_fields_round_trip() {
+        let mut out = String::new();
+        push_row(
+            &mut out,
+            &["/a/plain.txt", "/b/with, comma \"and\" quotes.txt"],
+        );
+        assert_eq!(
+            parse_rows(&out),
+            vec![vec![
+                "/a/plain.txt".to_string(),
+                "/b/with, comma \"and\" quotes.txt".to_string()
+            ]]
+        );
+    }
+}
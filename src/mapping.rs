@@ -0,0 +1,128 @@
+//! Sidecar mapping file pinning entries to named folders.
+//!
+//! Each line is `<file name> -> <folder>`; blank lines and lines starting with `#` are
+//! skipped. Folders are relative to the base path (or the output directory).
+//!
+//! A group map (`--group-map`) instead gives each file name a group key, as `name,key` CSV
+//! rows (with an optional `file,key` header) or a JSON object `{"name": "key", ...}`.
+
+use anyhow::{Context, Result, anyhow};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// Folders with the entries pinned to them.
+type Groups<T> = Vec<(String, Vec<T>)>;
+
+/// Read the mapping at `path` as file name -> folder.
+pub fn read(path: &Path) -> Result<BTreeMap<String, String>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed reading mapping {}", path.display()))?;
+    let mut pins = BTreeMap::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let malformed = || anyhow!("Malformed mapping line {} in {}", i + 1, path.display());
+        let (name, folder) = line.split_once("->").ok_or_else(malformed)?;
+        let (name, folder) = (name.trim(), folder.trim().trim_end_matches('/'));
+        if name.is_empty() || folder.is_empty() {
+            return Err(malformed());
+        }
+        if let Some(previous) = pins.insert(name.to_string(), folder.to_string())
+            && previous != folder
+        {
+            return Err(anyhow!(
+                "Mapping pins '{}' to both '{}' and '{}'",
+                name,
+                previous,
+                folder
+            ));
+        }
+    }
+    Ok(pins)
+}
+
+/// Read the group map at `path` as file name -> group key. A file whose first non-blank
+/// character is `{` is read as JSON, anything else as CSV.
+pub fn read_groups(path: &Path) -> Result<BTreeMap<String, String>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed reading group map {}", path.display()))?;
+    let groups = if content.trim_start().starts_with('{') {
+        crate::json::parse_string_object(&content)
+            .map_err(|err| anyhow!("Malformed group map {}: {}", path.display(), err))?
+    } else {
+        let mut groups = BTreeMap::new();
+        for (i, row) in crate::manifest::parse_rows(&content)
+            .into_iter()
+            .enumerate()
+        {
+            match row.as_slice() {
+                [blank] if blank.trim().is_empty() => {}
+                [name, key] if i == 0 && name == "file" && key == "key" => {}
+                [name, key] if !name.is_empty() => {
+                    groups.insert(name.clone(), key.clone());
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "Malformed group map row {} in {}",
+                        i + 1,
+                        path.display()
+                    ));
+                }
+            }
+        }
+        groups
+    };
+    // Keys become folder names, so they must not reach outside the target directory
+    if let Some(key) = groups
+        .values()
+        .find(|key| key.is_empty() || key.contains(['/', '\\']) || *key == "." || *key == "..")
+    {
+        return Err(anyhow!(
+            "Group map {} has key '{}', which is not a folder name",
+            path.display(),
+            key
+        ));
+    }
+    Ok(groups)
+}
+
+/// Split `files` into the pinned groups (folder -> entries) and the entries left to the
+/// automatic distribution. Every name in `pins` must match at least one entry.
+pub fn apply<T: AsRef<Path>>(
+    files: Vec<T>,
+    pins: &BTreeMap<String, String>,
+) -> Result<(Groups<T>, Vec<T>)> {
+    let mut pinned: BTreeMap<String, Vec<T>> = BTreeMap::new();
+    let mut used: BTreeSet<&str> = BTreeSet::new();
+    let mut rest = Vec::with_capacity(files.len());
+    for file in files {
+        let name = file
+            .as_ref()
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string());
+        match name.and_then(|n| pins.get_key_value(&n)) {
+            Some((name, folder)) => {
+                used.insert(name);
+                pinned.entry(folder.clone()).or_default().push(file);
+            }
+            None => rest.push(file),
+        }
+    }
+
+    let missing: Vec<&str> = pins
+        .keys()
+        .map(String::as_str)
+        .filter(|name| !used.contains(name))
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "Mapping lists entries that were not matched: {}",
+            missing.join(", ")
+        ));
+    }
+
+    Ok((pinned.into_iter().collect(), rest))
+}
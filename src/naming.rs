@@ -0,0 +1,476 @@
+//! Folder names and destination file name transforms.
+
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// How the bucket index is rendered after the folder prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SuffixStyle {
+    /// Decimal index: `group-1`, `group-2`, ... (the default).
+    #[default]
+    Numbers,
+    /// Bijective base-26: `group-a` ... `group-z`, `group-aa`.
+    Letters,
+    /// Lowercase hexadecimal: `group-9`, `group-a`, ... `group-f`, `group-10`.
+    Hex,
+    /// Lowercase roman numerals: `group-i`, `group-ii`, ... up to 3999.
+    Roman,
+    /// English words joined by hyphens: `group-one`, `group-twenty-one`.
+    Words,
+    /// Prefix only.
+    None,
+}
+
+impl FromStr for SuffixStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "numbers" => Ok(SuffixStyle::Numbers),
+            "letters" => Ok(SuffixStyle::Letters),
+            "hex" => Ok(SuffixStyle::Hex),
+            "roman" => Ok(SuffixStyle::Roman),
+            "words" => Ok(SuffixStyle::Words),
+            "none" => Ok(SuffixStyle::None),
+            other => Err(anyhow!(
+                "Unknown suffix style '{}'. Use numbers|letters|hex|roman|words|none",
+                other
+            )),
+        }
+    }
+}
+
+/// Zero-padding for numeric folder suffixes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pad {
+    /// Pad to at least this many digits; `0` for no padding (the default).
+    Width(usize),
+    /// Pad to the number of digits of the largest index in the run, so `group-01` ...
+    /// `group-12` sort correctly. The width changes as the folder count grows. Hex suffixes
+    /// count hex digits.
+    Auto,
+}
+
+impl Default for Pad {
+    fn default() -> Self {
+        Pad::Width(0)
+    }
+}
+
+impl Pad {
+    /// The absolute width for a run whose folders are numbered up to `last_index` in `style`.
+    pub fn width(self, last_index: usize, style: SuffixStyle) -> usize {
+        match self {
+            Pad::Width(width) => width,
+            Pad::Auto if style == SuffixStyle::Hex => format!("{:x}", last_index).len(),
+            Pad::Auto => last_index.to_string().len(),
+        }
+    }
+}
+
+impl FromStr for Pad {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(Pad::Auto),
+            other => other
+                .parse()
+                .map(Pad::Width)
+                .map_err(|_| anyhow!("Unknown padding '{}'. Use auto or a width", other)),
+        }
+    }
+}
+
+/// Name of the folder for the 0-based bucket `index`, exactly as `run` would create it.
+///
+/// The rendered number is `start_index + index`, joined to `prefix` with `separator`. `pad`
+/// zero-pads `Numbers` and `Hex` to at least that many digits (`0` for no padding) and is
+/// ignored by the other styles.
+///
+/// ```
+/// use refolder::{SuffixStyle, folder_name};
+///
+/// assert_eq!(folder_name("group", 0, SuffixStyle::Numbers, "-", 1, 0).unwrap(), "group-1");
+/// assert_eq!(folder_name("group", 4, SuffixStyle::Numbers, "_", 1, 3).unwrap(), "group_005");
+/// assert_eq!(folder_name("group", 26, SuffixStyle::Letters, "-", 1, 0).unwrap(), "group-aa");
+/// assert_eq!(folder_name("group", 15, SuffixStyle::Hex, "-", 1, 2).unwrap(), "group-10");
+/// assert_eq!(folder_name("group", 13, SuffixStyle::Roman, "-", 1, 0).unwrap(), "group-xiv");
+/// assert_eq!(folder_name("group", 20, SuffixStyle::Words, "-", 1, 0).unwrap(), "group-twenty-one");
+/// assert_eq!(folder_name("group", 3, SuffixStyle::None, "-", 1, 0).unwrap(), "group");
+/// ```
+///
+/// Letters and roman numerals have no zero, so a `start_index` of `0` is an error for them on
+/// the first bucket.
+pub fn folder_name(
+    prefix: &str,
+    index: usize,
+    style: SuffixStyle,
+    separator: &str,
+    start_index: usize,
+    pad: usize,
+) -> Result<String> {
+    let n = start_index
+        .checked_add(index)
+        .ok_or_else(|| anyhow!("Folder index overflows: {} + {}", start_index, index))?;
+    let suffix = match style {
+        SuffixStyle::Numbers => format!("{:0width$}", n, width = pad),
+        SuffixStyle::Hex => format!("{:0width$x}", n, width = pad),
+        SuffixStyle::Letters => {
+            if n == 0 {
+                return Err(anyhow!("Letter suffixes start at 1, got index 0"));
+            }
+            // index to letters: 1 -> a, 2 -> b, ... 27 -> aa
+            let mut i = n;
+            let mut s = String::new();
+            while i > 0 {
+                i -= 1; // 0-based
+                let ch = ((i % 26) as u8 + b'a') as char;
+                s.insert(0, ch);
+                i /= 26;
+            }
+            s
+        }
+        SuffixStyle::Roman => roman(n)?,
+        SuffixStyle::Words => words(n),
+        SuffixStyle::None => return Ok(prefix.to_string()),
+    };
+    Ok(format!("{}{}{}", prefix, separator, suffix))
+}
+
+/// Fill a folder name template: `{prefix}`, `{index}` (the rendered suffix) and `{count}`
+/// (entries in the folder). Other text is kept as is.
+///
+/// ```
+/// use refolder::render_template;
+///
+/// assert_eq!(render_template("{prefix}_{index}-[{count}files]", "batch", "2", 23), "batch_2-[23files]");
+/// ```
+pub fn render_template(template: &str, prefix: &str, index: &str, count: usize) -> String {
+    template
+        .replace("{prefix}", prefix)
+        .replace("{index}", index)
+        .replace("{count}", &count.to_string())
+}
+
+/// Fill a destination file name template: `{stem}` and `{ext}` of the original name,
+/// `{index}` (the entry's 1-based position in its folder) and `{folder}` (the folder's name).
+/// For a name without an extension, `.{ext}` is dropped as a whole.
+///
+/// ```
+/// use refolder::render_file_template;
+///
+/// assert_eq!(render_file_template("{folder}_{index}.{ext}", "IMG_0042.jpg", 3, "pack-1"), "pack-1_3.jpg");
+/// assert_eq!(render_file_template("{stem}-{index}.{ext}", "README", 1, "pack-1"), "README-1");
+/// ```
+pub fn render_file_template(template: &str, file_name: &str, index: usize, folder: &str) -> String {
+    let (stem, ext) = match file_name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, ext),
+        _ => (file_name, ""),
+    };
+    let template = match ext {
+        "" => template.replace(".{ext}", ""),
+        _ => template.to_string(),
+    };
+    template
+        .replace("{stem}", stem)
+        .replace("{ext}", ext)
+        .replace("{index}", &index.to_string())
+        .replace("{folder}", folder)
+}
+
+/// Render `time` (in UTC) with a small strftime subset: `%Y`, `%m`, `%d`, `%H`, `%M`, `%S`
+/// and `%%`. Any other specifier is an error.
+pub(crate) fn format_timestamp(format: &str, time: std::time::SystemTime) -> Result<String> {
+    let secs = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|_| anyhow!("Time is before the Unix epoch"))?
+        .as_secs();
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let (era, doe) = (z / 146_097, z % 146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    let mut out = String::with_capacity(format.len() + 8);
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", year)),
+            Some('m') => out.push_str(&format!("{:02}", month)),
+            Some('d') => out.push_str(&format!("{:02}", day)),
+            Some('H') => out.push_str(&format!("{:02}", rem / 3600)),
+            Some('M') => out.push_str(&format!("{:02}", rem % 3600 / 60)),
+            Some('S') => out.push_str(&format!("{:02}", rem % 60)),
+            Some('%') => out.push('%'),
+            other => {
+                return Err(anyhow!(
+                    "Unknown timestamp specifier '%{}'. Use %Y %m %d %H %M %S or %%",
+                    other.map(String::from).unwrap_or_default()
+                ));
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Lowercase roman numeral for `1..=3999`.
+fn roman(mut n: usize) -> Result<String> {
+    if !(1..=3999).contains(&n) {
+        return Err(anyhow!("Roman suffixes cover 1 to 3999, got index {}", n));
+    }
+    const NUMERALS: [(usize, &str); 13] = [
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+    let mut s = String::new();
+    for (value, numeral) in NUMERALS {
+        while n >= value {
+            s.push_str(numeral);
+            n -= value;
+        }
+    }
+    Ok(s)
+}
+
+/// English words for `n`, lowercase and hyphen-joined: 121 -> `one-hundred-twenty-one`.
+fn words(n: usize) -> String {
+    const ONES: [&str; 20] = [
+        "zero",
+        "one",
+        "two",
+        "three",
+        "four",
+        "five",
+        "six",
+        "seven",
+        "eight",
+        "nine",
+        "ten",
+        "eleven",
+        "twelve",
+        "thirteen",
+        "fourteen",
+        "fifteen",
+        "sixteen",
+        "seventeen",
+        "eighteen",
+        "nineteen",
+    ];
+    const TENS: [&str; 10] = [
+        "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+    ];
+    const SCALES: [&str; 7] = [
+        "",
+        "thousand",
+        "million",
+        "billion",
+        "trillion",
+        "quadrillion",
+        "quintillion",
+    ];
+
+    if n == 0 {
+        return ONES[0].to_string();
+    }
+
+    // Words for 1..=999
+    let below_thousand = |n: usize, parts: &mut Vec<&str>| {
+        if n >= 100 {
+            parts.push(ONES[n / 100]);
+            parts.push("hundred");
+        }
+        let rest = n % 100;
+        if rest >= 20 {
+            parts.push(TENS[rest / 10]);
+            if !rest.is_multiple_of(10) {
+                parts.push(ONES[rest % 10]);
+            }
+        } else if rest > 0 {
+            parts.push(ONES[rest]);
+        }
+    };
+
+    let mut groups = Vec::new();
+    let mut rest = n;
+    while rest > 0 {
+        groups.push(rest % 1000);
+        rest /= 1000;
+    }
+
+    let mut parts = Vec::new();
+    for (scale, &group) in groups.iter().enumerate().rev() {
+        if group == 0 {
+            continue;
+        }
+        below_thousand(group, &mut parts);
+        if scale > 0 {
+            parts.push(SCALES[scale]);
+        }
+    }
+    parts.join("-")
+}
+
+/// Case normalization applied to destination file names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenameCase {
+    /// Keep names as they are (the default).
+    #[default]
+    None,
+    /// Lowercase the whole name.
+    Lower,
+    /// Lowercase and replace runs of whitespace with a single hyphen.
+    Kebab,
+}
+
+impl RenameCase {
+    /// Apply the transform to a file name.
+    pub fn apply(self, name: &str) -> String {
+        match self {
+            RenameCase::None => name.to_string(),
+            RenameCase::Lower => name.to_lowercase(),
+            RenameCase::Kebab => name
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join("-")
+                .to_lowercase(),
+        }
+    }
+}
+
+impl FromStr for RenameCase {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(RenameCase::None),
+            "lower" => Ok(RenameCase::Lower),
+            "kebab" => Ok(RenameCase::Kebab),
+            other => Err(anyhow!(
+                "Unknown rename case '{}'. Use lower|kebab|none",
+                other
+            )),
+        }
+    }
+}
+
+/// Unicode normalization form applied to destination file names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeForm {
+    /// Composed (`é` as one code point), as Linux and Windows tools usually write.
+    Nfc,
+    /// Decomposed (`e` + combining accent), as older macOS file systems store names.
+    Nfd,
+}
+
+impl UnicodeForm {
+    /// Normalize a file name to this form.
+    pub fn apply(self, name: &str) -> String {
+        use unicode_normalization::UnicodeNormalization;
+        match self {
+            UnicodeForm::Nfc => name.nfc().collect(),
+            UnicodeForm::Nfd => name.nfd().collect(),
+        }
+    }
+}
+
+impl FromStr for UnicodeForm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "nfc" => Ok(UnicodeForm::Nfc),
+            "nfd" => Ok(UnicodeForm::Nfd),
+            other => Err(anyhow!(
+                "Unknown normalization form '{}'. Use nfc|nfd",
+                other
+            )),
+        }
+    }
+}
+
+/// What to do when two entries, or an entry and an existing file, want the same destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnCollision {
+    /// Refuse to plan two entries onto one destination. An existing file at the destination
+    /// is still an error at move time unless `--force` is given (the default).
+    #[default]
+    Error,
+    /// Add a `-N` counter to the file stem until the destination is free.
+    Rename,
+    /// Leave the entry where it is.
+    Skip,
+}
+
+impl FromStr for OnCollision {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "error" => Ok(OnCollision::Error),
+            "rename" => Ok(OnCollision::Rename),
+            "skip" => Ok(OnCollision::Skip),
+            other => Err(anyhow!(
+                "Unknown collision policy '{}'. Use error|rename|skip",
+                other
+            )),
+        }
+    }
+}
+
+/// First `dest` variant (`stem-1.ext`, `stem-2.ext`, ...) for which `taken` is false.
+pub fn unique_dest(dest: &Path, taken: impl Fn(&Path) -> bool) -> PathBuf {
+    let stem = dest
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ext = dest.extension().map(|e| e.to_string_lossy().to_string());
+    (1..)
+        .map(|n| {
+            let name = match &ext {
+                Some(ext) => format!("{}-{}.{}", stem, n, ext),
+                None => format!("{}-{}", stem, n),
+            };
+            dest.with_file_name(name)
+        })
+        .find(|candidate| !taken(candidate))
+        .expect("unbounded counter always finds a free name")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roman_and_words_edges() {
+        assert_eq!(roman(1994).unwrap(), "mcmxciv");
+        assert!(roman(0).is_err());
+        assert!(roman(4000).is_err());
+        assert_eq!(words(0), "zero");
+        assert_eq!(words(115), "one-hundred-fifteen");
+        assert_eq!(words(2_000_040), "two-million-forty");
+        assert!(folder_name("g", 0, SuffixStyle::Letters, "-", 0, 0).is_err());
+    }
+}
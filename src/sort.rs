@@ -0,0 +1,238 @@
+//! Orderings applied to collected entries before they are partitioned.
+
+use feruca::Collator;
+use regex::Regex;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::path::Path;
+
+/// Put collected entries into the order they are partitioned in.
+///
+/// Duplicates are dropped and the entries are byte-sorted first, so the later, stable
+/// orderings (`collate`, then `by_key`) only ever break ties the same way. The result depends
+/// only on the set of paths, never on the order the directory walk produced them in.
+///
+/// With `strip`, every ordering looks at file names with that leading text removed, so
+/// `DSC_0012.jpg` sorts as `0012.jpg`; the paths themselves are unchanged.
+pub fn order<T: AsRef<Path> + Ord>(
+    files: &mut Vec<T>,
+    collate_names: bool,
+    key: Option<&Regex>,
+    strip: Option<&str>,
+) {
+    files.sort();
+    files.dedup_by(|a, b| a.as_ref() == b.as_ref());
+    if let Some(prefix) = strip {
+        files.sort_by_cached_key(|f| stripped(f.as_ref(), prefix).into_owned());
+    }
+    if collate_names {
+        collate(files, strip);
+    }
+    if let Some(pattern) = key {
+        by_key(files, pattern, strip);
+    }
+}
+
+/// `path` with `prefix` removed from the start of its file name. Names that do not start
+/// with it, or consist of nothing else, are kept whole.
+fn stripped<'a>(path: &'a Path, prefix: &str) -> Cow<'a, Path> {
+    let rest = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .and_then(|n| n.strip_prefix(prefix))
+        .filter(|rest| !rest.is_empty());
+    match rest {
+        Some(rest) => Cow::Owned(path.with_file_name(rest)),
+        None => Cow::Borrowed(path),
+    }
+}
+
+/// `path` as the orderings see it: stripped of `strip` if given.
+fn sort_view<'a>(path: &'a Path, strip: Option<&str>) -> Cow<'a, Path> {
+    match strip {
+        Some(prefix) => stripped(path, prefix),
+        None => Cow::Borrowed(path),
+    }
+}
+
+/// Sort `files` with the Unicode Collation Algorithm (CLDR root order), so accented names
+/// sort next to their unaccented forms (`éclair` right after `eclair`, not after `zebra`).
+///
+/// Paths are compared component by component, like `Path`'s own ordering. The sort is
+/// stable, so entries that collate equal keep their existing (byte) order.
+pub fn collate<T: AsRef<Path>>(files: &mut [T], strip: Option<&str>) {
+    let mut collator = Collator::default();
+    files.sort_by(|a, b| {
+        compare_collated(
+            &mut collator,
+            &sort_view(a.as_ref(), strip),
+            &sort_view(b.as_ref(), strip),
+        )
+    });
+}
+
+fn compare_collated(collator: &mut Collator, a: &Path, b: &Path) -> Ordering {
+    let mut a_parts = a.components();
+    let mut b_parts = b.components();
+    loop {
+        match (a_parts.next(), b_parts.next()) {
+            (Some(x), Some(y)) => {
+                let x = x.as_os_str().to_string_lossy();
+                let y = y.as_os_str().to_string_lossy();
+                match collator.collate(x.as_ref(), y.as_ref()) {
+                    Ordering::Equal => {}
+                    other => return other,
+                }
+            }
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (None, None) => return Ordering::Equal,
+        }
+    }
+}
+
+/// A sort key extracted from a file name by `by_key`.
+#[derive(Debug)]
+enum Key {
+    Number(f64),
+    Text(String),
+    /// The pattern did not match; sorts after every matched name.
+    Missing,
+}
+
+/// Stable-sort `files` by the part of each file name captured by `pattern`: the first
+/// capture group if the pattern has one, otherwise the whole match.
+///
+/// Keys that parse as numbers compare numerically and come before text keys; names the
+/// pattern does not match go last. Equal keys keep their existing order.
+pub fn by_key<T: AsRef<Path>>(files: &mut [T], pattern: &Regex, strip: Option<&str>) {
+    files.sort_by_cached_key(|path| KeyOrd(extract_key(&sort_view(path.as_ref(), strip), pattern)));
+}
+
+fn extract_key(path: &Path, pattern: &Regex) -> Key {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    let Some(caps) = pattern.captures(&name) else {
+        return Key::Missing;
+    };
+    let Some(m) = caps.get(1).or_else(|| caps.get(0)) else {
+        return Key::Missing;
+    };
+    match m.as_str().parse::<f64>() {
+        Ok(n) if n.is_finite() => Key::Number(n),
+        _ => Key::Text(m.as_str().to_string()),
+    }
+}
+
+/// Total order over `Key`, with `f64::total_cmp` for numbers.
+struct KeyOrd(Key);
+
+impl PartialEq for KeyOrd {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for KeyOrd {}
+
+impl PartialOrd for KeyOrd {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for KeyOrd {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (&self.0, &other.0) {
+            (Key::Number(a), Key::Number(b)) => a.total_cmp(b),
+            (Key::Text(a), Key::Text(b)) => a.cmp(b),
+            (Key::Missing, Key::Missing) => Ordering::Equal,
+            (Key::Number(_), _) | (Key::Text(_), Key::Missing) => Ordering::Less,
+            _ => Ordering::Greater,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Strategy, assign};
+    use std::path::PathBuf;
+
+    #[test]
+    fn order_ignores_enumeration_order() {
+        let names = [
+            "b/scan_2.png",
+            "a/scan_2.png",
+            "éclair_7.png",
+            "eclair_7.png",
+            "scan_10.png",
+            "cover.png",
+            "scan_1.png",
+            "Zed_3.png",
+        ];
+        let pattern = Regex::new(r"_(\d+)").unwrap();
+        let ordered = |input: Vec<&str>| {
+            let mut files: Vec<PathBuf> = input.into_iter().map(PathBuf::from).collect();
+            order(&mut files, true, Some(&pattern), None);
+            assign(files, 3, Strategy::Count)
+        };
+
+        let expected = ordered(names.to_vec());
+        let mut shuffled = names.to_vec();
+        for _ in 0..names.len() {
+            shuffled.rotate_left(3);
+            shuffled.swap(0, 5);
+            // A duplicate, as when a redo folder is also seen by a recursive walk
+            let mut with_duplicate = shuffled.clone();
+            with_duplicate.push(shuffled[2]);
+            assert_eq!(ordered(with_duplicate), expected);
+        }
+        assert_eq!(expected[&0][0], PathBuf::from("scan_1.png"));
+    }
+
+    #[test]
+    fn strip_prefix_orders_by_the_rest_of_the_name() {
+        let mut files: Vec<PathBuf> = ["DSC_zeta.jpg", "beta.jpg", "DSC_alpha.jpg", "DSC_"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        order(&mut files, false, None, Some("DSC_"));
+        assert_eq!(
+            files,
+            ["DSC_", "DSC_alpha.jpg", "beta.jpg", "DSC_zeta.jpg"]
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        );
+
+        let pattern = Regex::new(r"^(\d+)").unwrap();
+        let mut numbered: Vec<PathBuf> = ["IMG_10.jpg", "IMG_9.jpg"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        by_key(&mut numbered, &pattern, Some("IMG_"));
+        assert_eq!(
+            numbered,
+            [PathBuf::from("IMG_9.jpg"), PathBuf::from("IMG_10.jpg")]
+        );
+    }
+
+    #[test]
+    fn sort_key_orders_numerically() {
+        let mut files: Vec<PathBuf> = ["scan_3.png", "cover.png", "scan_12.png", "scan_1.png"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        by_key(&mut files, &Regex::new(r"_(\d+)").unwrap(), None);
+        assert_eq!(
+            files,
+            ["scan_1.png", "scan_3.png", "scan_12.png", "cover.png"]
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        );
+    }
+}
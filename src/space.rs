@@ -0,0 +1,270 @@
+//! Filesystem preflights: write access for every run, free space for `--check-space` and
+//! device checks for `--same-filesystem-only`.
+
+use crate::{Plan, format_bytes};
+use anyhow::{Context, Result, anyhow};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Space a run needs on one target filesystem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Requirement {
+    /// An existing directory on that filesystem, to query its free space.
+    pub probe: PathBuf,
+    pub bytes: u64,
+}
+
+/// Fail before anything moves if a target filesystem lacks the space the plan needs.
+pub(crate) fn check(plan: &Plan) -> Result<()> {
+    let mut short = Vec::new();
+    for need in required(plan, device_id)?.into_values() {
+        let available = fs2::available_space(&need.probe)
+            .with_context(|| format!("Failed reading free space for {}", need.probe.display()))?;
+        if available < need.bytes {
+            short.push(format!(
+                "  {}: needs {}, {} available",
+                need.probe.display(),
+                format_bytes(need.bytes),
+                format_bytes(available)
+            ));
+        }
+    }
+    if short.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!("Not enough free space:\n{}", short.join("\n")))
+}
+
+/// Fail before anything moves if an entry would cross filesystems, where a move becomes a
+/// copy and delete.
+pub(crate) fn check_same_filesystem(plan: &Plan) -> Result<()> {
+    let mut crossing = Vec::new();
+    for folder in &plan.folders {
+        for m in folder.moves.iter().filter(|m| !m.is_noop()) {
+            if !same_filesystem(&m.src, &folder.path)? {
+                crossing.push(format!("  {} -> {}", m.src.display(), m.dest.display()));
+            }
+        }
+    }
+    if crossing.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "{} entries would move across filesystems:\n{}",
+        crossing.len(),
+        crossing.join("\n")
+    ))
+}
+
+/// Fail before anything moves if a directory the plan writes to is not writable: the folders
+/// entries leave, and each target folder (or, if it does not exist yet, the directory it
+/// will be created in). Each directory is probed by creating and removing a file in it.
+pub(crate) fn check_writable(plan: &Plan) -> Result<()> {
+    let mut dirs = BTreeSet::new();
+    for folder in &plan.folders {
+        let moves: Vec<_> = folder.moves.iter().filter(|m| !m.is_noop()).collect();
+        if moves.is_empty() {
+            continue;
+        }
+        dirs.insert(existing_ancestor(&folder.path)?);
+        dirs.extend(
+            moves
+                .iter()
+                .filter_map(|m| m.src.parent())
+                .map(Path::to_path_buf),
+        );
+    }
+    let unwritable: Vec<String> = dirs
+        .iter()
+        .filter_map(|dir| {
+            probe_write(dir)
+                .err()
+                .map(|err| format!("  {}: {}", dir.display(), err))
+        })
+        .collect();
+    if unwritable.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "Cannot write to {} directories:\n{}",
+        unwritable.len(),
+        unwritable.join("\n")
+    ))
+}
+
+fn probe_write(dir: &Path) -> std::io::Result<()> {
+    let probe = dir.join(format!(".refolder-probe-{}", std::process::id()));
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    fs::remove_file(&probe)
+}
+
+/// Whether `a` and `b` are on the same filesystem. A path that does not exist yet is judged
+/// by its closest existing ancestor.
+pub(crate) fn same_filesystem(a: &Path, b: &Path) -> Result<bool> {
+    Ok(device_id(&existing_ancestor(a)?)? == device_id(&existing_ancestor(b)?)?)
+}
+
+/// Bytes each target filesystem (keyed by `device`) has to take in. Only files that cross
+/// filesystems are counted: they are copied, while a rename on the same filesystem needs
+/// no space. Directories cannot cross filesystems at all and are left to fail at move time.
+pub(crate) fn required(
+    plan: &Plan,
+    device: impl Fn(&Path) -> Result<u64>,
+) -> Result<BTreeMap<u64, Requirement>> {
+    let mut needs: BTreeMap<u64, Requirement> = BTreeMap::new();
+    for folder in &plan.folders {
+        let probe = existing_ancestor(&folder.path)?;
+        let target = device(&probe)?;
+        for m in folder.moves.iter().filter(|m| !m.is_noop()) {
+            // Entries that have gone since the walk are skipped at move time
+            let Ok(meta) = fs::metadata(&m.src) else {
+                continue;
+            };
+            if meta.is_dir() || device(&m.src)? == target {
+                continue;
+            }
+            needs
+                .entry(target)
+                .or_insert_with(|| Requirement {
+                    probe: probe.clone(),
+                    bytes: 0,
+                })
+                .bytes += meta.len();
+        }
+    }
+    Ok(needs)
+}
+
+/// The closest ancestor of `path` (or `path` itself) that already exists; target folders
+/// are usually not created yet.
+fn existing_ancestor(path: &Path) -> Result<PathBuf> {
+    path.ancestors()
+        .find(|a| a.exists())
+        .map(Path::to_path_buf)
+        .ok_or_else(|| anyhow!("No existing ancestor of {}", path.display()))
+}
+
+#[cfg(unix)]
+fn device_id(path: &Path) -> Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(fs::metadata(path)
+        .with_context(|| format!("Failed reading metadata for {}", path.display()))?
+        .dev())
+}
+
+/// Without device ids, each path prefix (drive letter or share) stands for a filesystem.
+#[cfg(not(unix))]
+fn device_id(path: &Path) -> Result<u64> {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+    let mut hasher = DefaultHasher::new();
+    path.components().next().hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PlannedFolder, PlannedMove};
+    use tempfile::tempdir;
+
+    #[cfg(unix)]
+    #[test]
+    fn check_writable_names_the_read_only_directory() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempdir()?;
+        let locked = tmp.path().join("locked");
+        fs::create_dir(&locked)?;
+        fs::write(locked.join("a.txt"), "x")?;
+        let plan = Plan {
+            folders: vec![PlannedFolder {
+                path: tmp.path().join("group-1"),
+                moves: vec![PlannedMove::new(
+                    locked.join("a.txt"),
+                    tmp.path().join("group-1/a.txt"),
+                )],
+            }],
+            ..Default::default()
+        };
+        check_writable(&plan)?;
+
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o555))?;
+        let result = check_writable(&plan);
+        // Root writes anywhere, so the read-only directory cannot be simulated
+        let privileged = probe_write(&locked).is_ok();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755))?;
+        if privileged {
+            return Ok(());
+        }
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains(&locked.display().to_string()), "{}", err);
+        assert!(!err.contains("group-1"), "{}", err);
+        assert!(locked.join("a.txt").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn same_filesystem_compares_devices() -> Result<()> {
+        let tmp = tempdir()?;
+        let file = tmp.path().join("a.txt");
+        fs::write(&file, "x")?;
+        // Not created yet: judged by the temp directory
+        assert!(same_filesystem(&file, &tmp.path().join("group-1/a.txt"))?);
+        #[cfg(target_os = "linux")]
+        assert!(!same_filesystem(&file, Path::new("/proc/self"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn required_counts_cross_device_files() -> Result<()> {
+        let tmp = tempdir()?;
+        let (src, out) = (tmp.path().join("src"), tmp.path().join("out"));
+        fs::create_dir_all(src.join("sub"))?;
+        fs::create_dir(&out)?;
+        let sizes = [10, 200, 3000];
+        let mut moves = Vec::new();
+        for (i, size) in sizes.iter().enumerate() {
+            let file = src.join(format!("f{}.bin", i));
+            fs::write(&file, vec![0u8; *size])?;
+            let dest = out.join("group-1").join(file.file_name().unwrap());
+            moves.push(PlannedMove::new(file, dest));
+        }
+        // Directories and no-ops never need space
+        moves.push(PlannedMove::new(src.join("sub"), out.join("group-1/sub")));
+        let kept = src.join("f0.bin");
+        let plan = Plan {
+            folders: vec![
+                PlannedFolder {
+                    path: out.join("group-1"),
+                    moves,
+                },
+                PlannedFolder {
+                    path: src.clone(),
+                    moves: vec![PlannedMove::new(kept.clone(), kept)],
+                },
+            ],
+            ..Default::default()
+        };
+
+        // `out` stands in for another filesystem
+        let split = |p: &Path| Ok(u64::from(p.starts_with(&out)));
+        let needs = required(&plan, split)?;
+        assert_eq!(
+            needs,
+            BTreeMap::from([(
+                1,
+                Requirement {
+                    probe: out.clone(),
+                    bytes: sizes.iter().sum::<usize>() as u64,
+                }
+            )])
+        );
+
+        assert!(required(&plan, |_| Ok(0))?.is_empty());
+        Ok(())
+    }
+}
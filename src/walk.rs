@@ -0,0 +1,96 @@
+//! Parallel directory walk used by `collect_files` when `--threads` is given.
+
+use anyhow::{Result, anyhow};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::{Match, WalkBuilder, WalkState};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Walker patterns for `pattern` and `extra` (where `!glob` drops what `glob` matches) plus
+/// a directory-only negation for each `prune` glob. If every pattern is a negation, `*` is
+/// added first so there is something to drop entries from. Both walkers stop descending into
+/// a directory whose last matching pattern is a negation, so pruned subtrees are never read.
+pub fn patterns(pattern: &str, extra: &[String], prune: &[String]) -> Vec<String> {
+    let given: Vec<String> = std::iter::once(pattern.to_string())
+        .chain(extra.iter().cloned())
+        .collect();
+    let everything = given
+        .iter()
+        .all(|p| p.starts_with('!'))
+        .then(|| "*".to_string());
+    everything
+        .into_iter()
+        .chain(given)
+        .chain(prune.iter().map(|glob| format!("!{}/", glob)))
+        .collect()
+}
+
+/// Build the same matcher `globwalk` uses for `patterns`, so both walkers agree on what matches.
+fn glob_matcher(base: &Path, patterns: &[String], case_insensitive: bool) -> Result<Override> {
+    let mut builder = OverrideBuilder::new(base);
+    builder
+        .case_insensitive(case_insensitive)
+        .map_err(|e| anyhow!("Invalid pattern: {}", e))?;
+    for pattern in patterns {
+        // globwalk rewrites a bare `*` to `/*` so it only matches at the top level; mirror that.
+        let pattern = if pattern == "*" { "/*" } else { pattern };
+        builder
+            .add(pattern)
+            .map_err(|e| anyhow!("Invalid pattern '{}': {}", pattern, e))?;
+    }
+    builder
+        .build()
+        .map_err(|e| anyhow!("Invalid pattern: {}", e))
+}
+
+/// Walk `base` with `threads` worker threads, returning every entry matching `patterns` down to
+/// `max_depth`, plus the errors for entries that could not be read. Both lists are sorted, so the
+/// result does not depend on thread scheduling.
+pub fn parallel_walk(
+    base: &Path,
+    patterns: &[String],
+    case_insensitive: bool,
+    max_depth: usize,
+    threads: usize,
+) -> Result<(Vec<PathBuf>, Vec<String>)> {
+    let matcher = glob_matcher(base, patterns, case_insensitive)?;
+    let found: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+    let errors: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    WalkBuilder::new(base)
+        .standard_filters(false)
+        .max_depth(Some(max_depth))
+        .threads(threads)
+        .build_parallel()
+        .run(|| {
+            Box::new(|entry| {
+                let entry = match entry {
+                    Ok(e) => e,
+                    Err(err) => {
+                        errors.lock().unwrap().push(err.to_string());
+                        return WalkState::Continue;
+                    }
+                };
+                let Ok(rel) = entry.path().strip_prefix(base) else {
+                    return WalkState::Continue;
+                };
+                // The base directory itself is never a candidate.
+                if rel.as_os_str().is_empty() {
+                    return WalkState::Continue;
+                }
+                let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+                match matcher.matched(rel, is_dir) {
+                    Match::Whitelist(_) => found.lock().unwrap().push(entry.into_path()),
+                    Match::Ignore(_) if is_dir => return WalkState::Skip,
+                    _ => {}
+                }
+                WalkState::Continue
+            })
+        });
+
+    let mut found = found.into_inner().unwrap();
+    let mut errors = errors.into_inner().unwrap();
+    found.sort();
+    errors.sort();
+    Ok((found, errors))
+}
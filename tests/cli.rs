@@ -0,0 +1,139 @@
+//! End-to-end checks that run the `refolder` binary.
+
+use std::fs::{self, File};
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+fn refolder() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_refolder"))
+}
+
+/// The plan `--print-plan` writes to stderr, as JSON.
+fn printed_plan(stderr: &[u8]) -> anyhow::Result<serde_json::Value> {
+    let stderr = String::from_utf8(stderr.to_vec())?;
+    let line = stderr
+        .lines()
+        .find(|l| l.starts_with('{'))
+        .unwrap_or_else(|| panic!("no plan in {}", stderr));
+    Ok(serde_json::from_str(line)?)
+}
+
+#[test]
+fn print_plan_still_moves() -> anyhow::Result<()> {
+    let dir = tempdir()?;
+    let base = dir.path();
+    for i in 0..3 {
+        File::create(base.join(format!("file{}.txt", i)))?;
+    }
+
+    let output = refolder()
+        .arg(base)
+        .args(["--subfolders", "2", "--print-plan"])
+        .output()?;
+    assert!(output.status.success(), "{:?}", output);
+
+    // The plan is printed to stderr as one JSON line, alongside the log
+    let plan = printed_plan(&output.stderr)?;
+    let dests: Vec<&Path> = plan["folders"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .flat_map(|f| f["moves"].as_array().unwrap())
+        .map(|m| Path::new(m["dest"].as_str().unwrap()))
+        .collect();
+    assert_eq!(dests.len(), 3);
+    // The plan holds canonical paths, which may differ from the temp dir as given
+    let expected = fs::canonicalize(base)?.join("group-2").join("file2.txt");
+    assert!(dests.contains(&expected.as_path()), "{:?}", dests);
+
+    assert!(base.join("group-2").join("file2.txt").is_file());
+    Ok(())
+}
+
+#[test]
+fn run_with_relative_dot_base() -> anyhow::Result<()> {
+    let dir = tempdir()?;
+    let root = fs::canonicalize(dir.path())?;
+    fs::create_dir_all(root.join("sub"))?;
+    for i in 0..4 {
+        File::create(root.join(format!("f{}.txt", i)))?;
+        File::create(root.join("sub").join(format!("s{}.txt", i)))?;
+    }
+    // The working directory is the child's own, so this does not disturb other tests
+    let run = |base: &str, extra: &[&str]| -> anyhow::Result<std::process::Output> {
+        let output = refolder()
+            .current_dir(&root)
+            .arg(base)
+            .args([
+                "--matching",
+                "*.txt",
+                "--subfolders",
+                "2",
+                "--prefix",
+                "pack",
+            ])
+            .args(extra)
+            .output()?;
+        assert!(output.status.success(), "{:?}", output);
+        Ok(output)
+    };
+
+    run(".", &[])?;
+    run("./sub", &["--no-canonicalize"])?;
+    assert!(root.join("pack-1/f0.txt").is_file());
+    assert!(root.join("pack-2/f3.txt").is_file());
+    assert!(root.join("sub/pack-1/s0.txt").is_file());
+    assert!(root.join("sub/pack-2/s3.txt").is_file());
+
+    // Destinations are absolute and the redo is a no-op
+    let plan = printed_plan(&run("./sub", &["--dry-run", "--print-plan"])?.stderr)?;
+    let moves: Vec<&serde_json::Value> = plan["folders"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .flat_map(|f| f["moves"].as_array().unwrap())
+        .collect();
+    assert_eq!(moves.len(), 4);
+    for m in moves {
+        assert!(Path::new(m["dest"].as_str().unwrap()).is_absolute());
+        assert_eq!(m["src"], m["dest"], "{}", m);
+    }
+    Ok(())
+}
+
+#[test]
+fn a_failing_single_base_reports_one_error() -> anyhow::Result<()> {
+    let dir = tempdir()?;
+    File::create(dir.path().join("a.log"))?;
+
+    let output = refolder()
+        .arg(dir.path())
+        .args(["--matching", "*.txt", "--subfolders", "2"])
+        .args(["--no-op-on-empty-match", "error"])
+        .output()?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr)?;
+    assert_eq!(stderr.matches("Error:").count(), 1, "{}", stderr);
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(!stdout.contains("Total bytes moved"), "{}", stdout);
+    Ok(())
+}
+
+#[test]
+fn no_total_after_nothing_matched() -> anyhow::Result<()> {
+    let dir = tempdir()?;
+    File::create(dir.path().join("a.log"))?;
+    let run = |matching: &str| -> anyhow::Result<String> {
+        let output = refolder()
+            .arg(dir.path())
+            .args(["--matching", matching, "--subfolders", "2"])
+            .output()?;
+        // Nothing matching exits with its own code, so the status is not checked
+        Ok(String::from_utf8(output.stdout)?)
+    };
+
+    assert!(!run("*.txt")?.contains("Total bytes moved"));
+    assert!(run("*.log")?.contains("Total bytes moved"));
+    Ok(())
+}